@@ -0,0 +1,53 @@
+use palantir_agent_lib::config::defs::ReporterConfig;
+use palantir_agent_lib::workers::registry::apm::{builtin_exporter, run_registry};
+use palantir_agent_lib::workers::registry::exporter::{ExportFuture, Exporter, Snapshot};
+use std::collections::BTreeMap;
+use std::sync::mpsc::sync_channel;
+use std::sync::{Arc, Mutex};
+
+/// prints a line per report instead of pushing it anywhere
+struct StdoutExporter;
+
+impl Exporter for StdoutExporter {
+    fn export<'a>(
+        &'a mut self,
+        _config: &'a ReporterConfig,
+        snapshot: &'a Snapshot,
+    ) -> ExportFuture<'a> {
+        println!(
+            "report at {}: {} collections, {} requests handled",
+            snapshot.timestamp_ms(),
+            snapshot.collections.len(),
+            snapshot.handle_time.count()
+        );
+        Box::pin(async {})
+    }
+}
+
+// registry without listeners, "stdout" target is exported by the example exporter,
+// any other one would be pushed to its configured destination
+fn main() {
+    let config: ReporterConfig = serde_yaml::from_str(
+        "vm_import_url: http://localhost:8428/api/v1/import/prometheus\nperiod_seconds: 5",
+    )
+    .unwrap();
+    let mut targets = BTreeMap::new();
+    targets.insert("stdout".to_string(), config);
+
+    // nothing is sent, sender is only kept so that processor keeps waiting for messages
+    let (_tx, rx) = sync_channel(1024);
+    run_registry(
+        rx,
+        Arc::new(Mutex::new(targets)),
+        None,
+        Box::new(
+            |target: &str, config: &ReporterConfig| -> Box<dyn Exporter> {
+                match target {
+                    "stdout" => Box::new(StdoutExporter),
+                    _ => builtin_exporter(target, config),
+                }
+            },
+        ),
+    )
+    .unwrap();
+}
@@ -1,56 +1,173 @@
-use lazy_static::lazy_static;
-use log::LevelFilter;
-use palantir_agent_lib::config::defs::{Config, ListenerType, ReporterConfig, UDPConfig};
-use palantir_agent_lib::workers::registry::apm::run_registry;
+use log::{error, info, warn, LevelFilter};
+use palantir_agent_lib::config::defs::{ReporterConfig, ScrapeConfig};
+use palantir_agent_lib::config::parser::load_config;
+use palantir_agent_lib::constants::{CONFIG_PATH_ENV, PIPELINE_CAPACITY};
+use palantir_agent_lib::workers::registry::apm::{builtin_exporter, run_registry};
+use palantir_agent_lib::workers::registry::scrape::bind as bind_scrape;
 use palantir_agent_lib::workers::server::Server;
 use simple_logger::SimpleLogger;
-use std::sync::mpsc::channel;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::sync_channel;
+use std::sync::{Arc, Mutex};
 use std::thread;
+use structopt::StructOpt;
+use tokio::signal::unix::{signal, SignalKind};
 
-fn main() {
+/// APM agent aggregating client events into histograms reported to VictoriaMetrics
+#[derive(StructOpt)]
+#[structopt(name = "palantir-agent")]
+struct Cli {
+    /// `run` if omitted
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Start the agent
+    Run {
+        /// Config file path
+        #[structopt(env = CONFIG_PATH_ENV)]
+        config: PathBuf,
+    },
+    /// Check config file, exits with non-zero code if it's invalid
+    Validate {
+        /// Config file path
+        #[structopt(env = CONFIG_PATH_ENV)]
+        config: PathBuf,
+    },
+    /// Print effective config: file with env overrides applied and defaults filled in
+    PrintConfig {
+        /// Config file path
+        #[structopt(env = CONFIG_PATH_ENV)]
+        config: PathBuf,
+    },
+}
+
+/// re-reads config file, old config stays in effect if new one is invalid
+fn reload(
+    path: &Path,
+    server: &mut Server,
+    targets: &Mutex<BTreeMap<String, ReporterConfig>>,
+    scrape: &Option<ScrapeConfig>,
+) {
+    info!("Reloading config from {:?}", path);
+    let config = match load_config(path, std::env::vars()) {
+        Ok(config) => config,
+        Err(err) => {
+            error!("Unable to reload config, keeping the old one, {}", err);
+            return;
+        }
+    };
+
+    // listeners that failed to bind are retried on next reload
+    if let Err(err) = server.apply(&config.listeners) {
+        error!("Unable to apply listeners config, {:?}", err);
+    }
+    *targets.lock().unwrap() = config.all_targets();
+    if config.scrape != *scrape {
+        warn!("Scrape endpoint changes take effect after restart");
+    }
+    info!("Config reloaded");
+}
+
+fn validate(path: &Path) {
+    match load_config(path, std::env::vars()) {
+        Ok(_) => println!("{:?} is valid", path),
+        Err(err) => {
+            eprintln!("{:?} is invalid, {}", path, err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn print_config(path: &Path) {
+    let config = match load_config(path, std::env::vars()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{:?} is invalid, {}", path, err);
+            std::process::exit(1);
+        }
+    };
+    match serde_yaml::to_string(&config) {
+        Ok(raw_config) => println!("{}", raw_config),
+        Err(err) => {
+            eprintln!("Unable to serialize config, {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run(path: &Path) {
     SimpleLogger::new()
         .with_level(LevelFilter::Trace)
+        // handshake details of every push otherwise
+        .with_module_level("rustls", LevelFilter::Warn)
         .init()
         .unwrap();
 
-    lazy_static! {
-        static ref CONFIG: Config = Config {
-            listeners: vec![
-                ListenerType::UDP(UDPConfig {
-                    port: 5545,
-                    buffer_size: 4096,
-                }),
-                ListenerType::UDP(UDPConfig {
-                    port: 5546,
-                    buffer_size: 4096,
-                }),
-            ],
-            reporter: ReporterConfig {
-                vm_import_url: "http://localhost:8428/api/v1/import/prometheus".to_string(),
-            },
-        };
-    }
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Unable to create runtime");
+    // registered first, SIGHUP would terminate the agent while it's starting up otherwise
+    let mut hangup = runtime
+        .block_on(async { signal(SignalKind::hangup()) })
+        .expect("Unable to handle SIGHUP");
 
-    let (tx, rx) = channel();
+    let config = match load_config(path, std::env::vars()) {
+        Ok(config) => config,
+        Err(err) => {
+            error!("Unable to load config from {:?}, {}", path, err);
+            std::process::exit(1);
+        }
+    };
+    info!("Loaded config from {:?}", path);
 
-    let server = Server::new(&CONFIG.listeners, tx);
-    let listener_handles = server.schedule().unwrap();
+    let (tx, rx) = sync_channel(PIPELINE_CAPACITY);
+
+    let mut server = Server::new(tx);
+    if let Err(err) = server.apply(&config.listeners) {
+        error!("Unable to start listeners, {:?}", err);
+        std::process::exit(1);
+    }
+
+    let scrape = match config.scrape.as_ref().map(bind_scrape) {
+        None => None,
+        Some(Ok(listener)) => Some(listener),
+        Some(Err(err)) => {
+            error!("Unable to start scrape endpoint, {:?}", err);
+            std::process::exit(1);
+        }
+    };
 
-    let registry_handler = thread::spawn(move || {
+    let targets = Arc::new(Mutex::new(config.all_targets()));
+    let registry_targets = targets.clone();
+    thread::spawn(move || {
         #[allow(unused_must_use)]
         {
-            run_registry(rx, &CONFIG.reporter);
+            run_registry(rx, registry_targets, scrape, Box::new(builtin_exporter));
         }
     });
 
-    for join_handle in listener_handles {
-        #[allow(unused_must_use)]
-        {
-            join_handle.join();
+    runtime.block_on(async {
+        while hangup.recv().await.is_some() {
+            reload(path, &mut server, &targets, &config.scrape);
         }
-    }
-    #[allow(unused_must_use)]
-    {
-        registry_handler.join();
+    });
+}
+
+fn main() {
+    let command = match Cli::from_args().command {
+        Some(command) => command,
+        // `run` is the default, config path comes from env then
+        None => Command::from_iter(vec!["palantir-agent", "run"]),
+    };
+
+    match command {
+        Command::Run { config } => run(&config),
+        Command::Validate { config } => validate(&config),
+        Command::PrintConfig { config } => print_config(&config),
     }
 }
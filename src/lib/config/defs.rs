@@ -1,36 +1,504 @@
-use serde::{Deserialize, Serialize};
+use crate::constants::DEFAULT_TARGET_NAME;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Config {
     pub listeners: Vec<ListenerType>,
     pub reporter: ReporterConfig,
+    /// more places to push to, each with its own schedule, credentials and filter
+    #[serde(default)]
+    pub targets: BTreeMap<String, ReporterConfig>,
+    /// serve metrics to Prometheus, not re-read on reload
+    #[serde(default)]
+    pub scrape: Option<ScrapeConfig>,
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+impl Config {
+    /// `targets` along with `reporter` named `default`
+    pub fn all_targets(&self) -> BTreeMap<String, ReporterConfig> {
+        let mut targets = self.targets.clone();
+        targets.insert(DEFAULT_TARGET_NAME.to_string(), self.reporter.clone());
+        targets
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ReporterConfig {
-    pub vm_import_url: String,
+    /// VictoriaMetrics `/api/v1/import/prometheus`, exactly one destination url is set
+    #[serde(default)]
+    pub vm_import_url: Option<String>,
+    /// Prometheus remote_write endpoint, e.g. Mimir or Thanos receive
+    #[serde(default)]
+    pub remote_write_url: Option<String>,
+    /// InfluxDB write endpoint with database (v1) or org and bucket (v2) in query
+    #[serde(default)]
+    pub influx_write_url: Option<String>,
+    /// `host:port` of Graphite plaintext listener
+    #[serde(default)]
+    pub graphite_address: Option<String>,
+    /// dotted Graphite path of client histograms, `{name}` is replaced with tag value
+    #[serde(default = "default_graphite_path_template")]
+    pub graphite_path_template: String,
+    /// OTLP/HTTP metrics endpoint of OpenTelemetry Collector, e.g. `http://collector:4318/v1/metrics`
+    #[serde(default)]
+    pub otlp_url: Option<String>,
+    #[serde(default)]
+    pub otlp_histogram: OtlpHistogram,
+    /// local directory to write reports to instead of pushing them
+    #[serde(default)]
+    pub file: Option<FileConfig>,
+    #[serde(default = "default_report_period")]
+    pub period_seconds: u64,
+    /// whole request, including connection and response, 5s or period if it's shorter by default
+    #[serde(default)]
+    pub request_timeout_ms: Option<u64>,
+    /// 2s or request timeout if it's shorter by default
+    #[serde(default)]
+    pub connect_timeout_ms: Option<u64>,
+    /// report on wall-clock multiples of period (`:00`, `:15`, `:30`, `:45` for 15s)
+    #[serde(default)]
+    pub align_to_period: bool,
+    /// upper bound of random schedule shift, picked once so reports stay evenly spaced
+    #[serde(default)]
+    pub jitter_ms: u64,
+    /// added to every series, `PALANTIR_LABEL_*` env variables are merged in on load
+    #[serde(default)]
+    pub extra_labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    /// payloads that couldn't be pushed are kept here and replayed, dropped if not set
+    #[serde(default)]
+    pub spool: Option<SpoolConfig>,
+    #[serde(default)]
+    pub compression: Compression,
+    /// report is split into several pushes of at most this size (before compression)
+    #[serde(default)]
+    pub max_payload_bytes: Option<u64>,
+    #[serde(default = "default_max_parallel_pushes")]
+    pub max_parallel_pushes: usize,
+    #[serde(default)]
+    pub auth: Option<AuthConfig>,
+    /// sent with every push, e.g. `AccountID` for multitenant setups
+    /// plain string is an inline value, values are redacted as secrets are
+    #[serde(default, deserialize_with = "deserialize_headers")]
+    pub headers: BTreeMap<String, Secret>,
+    /// used for https urls, bundled Mozilla roots are trusted if not set
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub filter: TargetFilter,
+}
+
+/// protocol of a target, picked by which destination is set
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExporterKind {
+    VmImport,
+    RemoteWrite,
+    Influx,
+    Graphite,
+    Otlp,
+    File,
+}
+
+impl ReporterConfig {
+    /// every set destination with its field name, validation keeps exactly one
+    pub fn destinations(&self) -> Vec<(ExporterKind, &'static str, &str)> {
+        let urls = [
+            (
+                ExporterKind::VmImport,
+                "vm_import_url",
+                self.vm_import_url.as_deref(),
+            ),
+            (
+                ExporterKind::RemoteWrite,
+                "remote_write_url",
+                self.remote_write_url.as_deref(),
+            ),
+            (
+                ExporterKind::Influx,
+                "influx_write_url",
+                self.influx_write_url.as_deref(),
+            ),
+            (
+                ExporterKind::Graphite,
+                "graphite_address",
+                self.graphite_address.as_deref(),
+            ),
+            (ExporterKind::Otlp, "otlp_url", self.otlp_url.as_deref()),
+            (
+                ExporterKind::File,
+                "file",
+                self.file
+                    .as_ref()
+                    .map(|file| file.dir.to_str().unwrap_or_default()),
+            ),
+        ];
+        urls.iter()
+            .filter_map(|(kind, field, url)| url.map(|url| (*kind, *field, url)))
+            .collect()
+    }
+
+    /// explicit timeout or default one that still fits into the period
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_millis(self.request_timeout_ms.unwrap_or_else(|| {
+            DEFAULT_REQUEST_TIMEOUT_MS.min(self.period_seconds.saturating_mul(1000).max(1))
+        }))
+    }
+
+    /// explicit timeout or default one that still fits into the request timeout
+    pub fn connect_timeout(&self) -> Duration {
+        let request_timeout_ms = self.request_timeout().as_millis() as u64;
+        Duration::from_millis(
+            self.connect_timeout_ms
+                .unwrap_or_else(|| DEFAULT_CONNECT_TIMEOUT_MS.min(request_timeout_ms)),
+        )
+    }
+
+    /// (protocol, url or address) of the target, None if config wasn't validated
+    pub fn destination(&self) -> Option<(ExporterKind, &str)> {
+        match self.destinations().as_slice() {
+            [(kind, _, url)] => Some((*kind, *url)),
+            _ => None,
+        }
+    }
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// collection is pushed if it matches every non-empty list, agent's own metrics are always pushed
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct TargetFilter {
+    #[serde(default)]
+    pub realms: Vec<String>,
+    #[serde(default)]
+    pub applications: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// PEM bundle replacing the default roots
+    #[serde(default)]
+    pub ca_file: Option<PathBuf>,
+    /// client certificate chain and key for mutual TLS, PEM
+    #[serde(default)]
+    pub cert_file: Option<PathBuf>,
+    #[serde(default)]
+    pub key_file: Option<PathBuf>,
+    /// sent in SNI and checked against server certificate instead of url host
+    #[serde(default)]
+    pub server_name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthConfig {
+    Basic {
+        username: String,
+        password: Secret,
+    },
+    Bearer(Secret),
+    /// `Authorization: Token ...` of InfluxDB v2
+    Token(Secret),
+}
+
+/// credential, inline values never show up in logs or `print-config` output
+/// file is re-read when it changes, env variable is read on every push
+#[derive(Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Secret {
+    Value(String),
+    File(PathBuf),
+    Env(String),
+}
+
+const REDACTED: &str = "<redacted>";
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Secret::Value(_) => f.debug_tuple("Value").field(&REDACTED).finish(),
+            Secret::File(path) => f.debug_tuple("File").field(path).finish(),
+            Secret::Env(name) => f.debug_tuple("Env").field(name).finish(),
+        }
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Secret::Value(_) => {
+                serializer.serialize_newtype_variant("Secret", 0, "value", REDACTED)
+            }
+            Secret::File(path) => serializer.serialize_newtype_variant("Secret", 1, "file", path),
+            Secret::Env(name) => serializer.serialize_newtype_variant("Secret", 2, "env", name),
+        }
+    }
+}
+
+/// header value, either plain string or secret
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HeaderValue {
+    Plain(String),
+    Secret(Secret),
+}
+
+fn deserialize_headers<'de, D>(deserializer: D) -> Result<BTreeMap<String, Secret>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let headers = BTreeMap::<String, HeaderValue>::deserialize(deserializer)?;
+    Ok(headers
+        .into_iter()
+        .map(|(name, value)| match value {
+            HeaderValue::Plain(value) => (name, Secret::Value(value)),
+            HeaderValue::Secret(secret) => (name, secret),
+        })
+        .collect())
+}
+
+fn default_graphite_path_template() -> String {
+    "{realm}.{application}.{action_name}.{span}".to_string()
+}
+
+fn default_max_parallel_pushes() -> usize {
+    4
+}
+
+/// `Content-Encoding` of report body
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn content_encoding(&self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Zstd => Some("zstd"),
+        }
+    }
+}
+
+/// how histograms are sent over OTLP, buckets are the same either way
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OtlpHistogram {
+    /// scale 0 `ExponentialHistogram`, first bucket becomes zero bucket
+    #[default]
+    Exponential,
+    /// `Histogram` with explicit bounds
+    Explicit,
+}
+
+/// exponential backoff between push attempts: initial, 2 * initial, ... up to max
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetryConfig {
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_initial_backoff")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "default_max_backoff")]
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_attempts(),
+            initial_backoff_ms: default_initial_backoff(),
+            max_backoff_ms: default_max_backoff(),
+        }
+    }
+}
+
+/// reports are appended to the current file, which is replaced once it's too large or too old
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileConfig {
+    pub dir: PathBuf,
+    #[serde(default)]
+    pub format: FileFormat,
+    /// on disk, so after compression
+    #[serde(default = "default_max_file_size")]
+    pub max_file_bytes: u64,
+    /// 0 -> files are rotated by size only
+    #[serde(default = "default_rotate_seconds")]
+    pub rotate_seconds: u64,
+    /// oldest files are removed when exceeded, current one included
+    #[serde(default = "default_max_files")]
+    pub max_files: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileFormat {
+    /// text exposition format with timestamps, as `/api/v1/import/prometheus` takes it
+    #[default]
+    Prometheus,
+    /// JSON line per series, as `/api/v1/import` takes it
+    Jsonl,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpoolConfig {
+    pub path: PathBuf,
+    /// oldest payloads are dropped when exceeded
+    #[serde(default = "default_spool_size")]
+    pub max_size_bytes: u64,
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_initial_backoff() -> u64 {
+    500
+}
+
+fn default_max_backoff() -> u64 {
+    5000
+}
+
+fn default_max_file_size() -> u64 {
+    64 * 1024 * 1024
+}
+
+fn default_rotate_seconds() -> u64 {
+    3600
+}
+
+fn default_max_files() -> u64 {
+    24
+}
+
+fn default_spool_size() -> u64 {
+    256 * 1024 * 1024
+}
+
+fn default_report_period() -> u64 {
+    10
+}
+
+const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 5000;
+
+const DEFAULT_CONNECT_TIMEOUT_MS: u64 = 2000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ListenerType {
     UDP(UDPConfig),
     TCP(TCPConfig),
+    UnixDatagram(UnixDatagramConfig),
+    UnixStream(UnixStreamConfig),
+    HTTP(HTTPConfig),
+}
+
+impl ListenerType {
+    /// address listener binds to, None for unix sockets
+    pub fn socket_address(&self) -> Option<SocketAddr> {
+        match self {
+            ListenerType::UDP(cfg) => Some(SocketAddr::new(cfg.address, cfg.port)),
+            ListenerType::TCP(cfg) => Some(SocketAddr::new(cfg.address, cfg.port)),
+            ListenerType::HTTP(cfg) => Some(SocketAddr::new(cfg.address, cfg.port)),
+            ListenerType::UnixDatagram(_) | ListenerType::UnixStream(_) => None,
+        }
+    }
+
+    /// socket file listener binds to, None for network sockets
+    pub fn socket_path(&self) -> Option<&PathBuf> {
+        match self {
+            ListenerType::UnixDatagram(cfg) => Some(&cfg.path),
+            ListenerType::UnixStream(cfg) => Some(&cfg.path),
+            ListenerType::UDP(_) | ListenerType::TCP(_) | ListenerType::HTTP(_) => None,
+        }
+    }
 }
 
 fn default_buffer_size() -> u16 {
     4096
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+fn default_max_connections() -> u16 {
+    256
+}
+
+fn default_read_timeout_ms() -> u64 {
+    30_000
+}
+
+fn default_max_body_size() -> u32 {
+    1024 * 1024
+}
+
+/// loopback only, use `0.0.0.0` or `::` (dual-stack) to accept remote clients
+fn default_address() -> IpAddr {
+    IpAddr::from(Ipv4Addr::LOCALHOST)
+}
+
+/// `GET /metrics` in Prometheus text format, histograms with `le` buckets
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScrapeConfig {
+    #[serde(default = "default_address")]
+    pub address: IpAddr,
+    pub port: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct UDPConfig {
+    #[serde(default = "default_address")]
+    pub address: IpAddr,
     pub port: u16,
     #[serde(default = "default_buffer_size")]
     pub buffer_size: u16,
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TCPConfig {
+    #[serde(default = "default_address")]
+    pub address: IpAddr,
     pub port: u16,
     #[serde(default = "default_buffer_size")]
     pub buffer_size: u16,
+    /// connections accepted beyond it are closed right away
+    #[serde(default = "default_max_connections")]
+    pub max_connections: u16,
+    /// connection that sends nothing for that long is closed
+    #[serde(default = "default_read_timeout_ms")]
+    pub read_timeout_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnixDatagramConfig {
+    pub path: PathBuf,
+    #[serde(default = "default_buffer_size")]
+    pub buffer_size: u16,
+    /// socket file mode (e.g. `0o660`), left to umask if not set
+    #[serde(default)]
+    pub mode: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnixStreamConfig {
+    pub path: PathBuf,
+    #[serde(default = "default_buffer_size")]
+    pub buffer_size: u16,
+    /// socket file mode (e.g. `0o660`), left to umask if not set
+    #[serde(default)]
+    pub mode: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HTTPConfig {
+    #[serde(default = "default_address")]
+    pub address: IpAddr,
+    pub port: u16,
+    #[serde(default = "default_max_body_size")]
+    pub max_body_size: u32,
 }
@@ -0,0 +1,311 @@
+//! Environment overrides for config file values
+//!
+//! `PALANTIR__` prefix followed by the path to the field, segments are separated by `__`:
+//! - `PALANTIR__REPORTER__VM_IMPORT_URL=http://vm:8428/api/v1/import/prometheus`
+//! - `PALANTIR__LISTENERS__0__PORT=5545` (listeners are addressed by index)
+//! - `PALANTIR__LISTENERS__0__UDP__PORT=5545` (listener type may be given explicitly)
+//!
+//! Values replacing strings are kept as they are. Values of other (or missing) fields
+//! are parsed as YAML scalars, so numbers and booleans keep their types and quotes
+//! (`PALANTIR__REPORTER__HEADERS__ACCOUNTID='"42"'`) force a string.
+//! Anything else, e.g. a value that reads as YAML mapping, is kept as a string.
+//! Listeners can only be overridden, not added.
+//!
+//! `PALANTIR_LABEL_POD=web-1` adds `pod="web-1"` to `reporter.extra_labels`,
+//! env labels take precedence over the ones from config file.
+
+use crate::config::parser::ConfigurationError;
+use crate::constants::{
+    CONFIG_ENV_PREFIX, CONFIG_ENV_SEPARATOR, EXTRA_LABEL_PREFIX, EXTRA_LABEL_REGEX,
+};
+use log::{info, warn};
+use serde_yaml::{Mapping, Value};
+use std::collections::BTreeMap;
+
+/// applies every `PALANTIR__*` variable to raw config
+pub fn apply_env_overrides<I>(config: &mut Value, vars: I) -> Result<(), ConfigurationError>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let mut overrides: Vec<(String, String)> = vars
+        .into_iter()
+        .filter(|(key, _)| key.starts_with(CONFIG_ENV_PREFIX))
+        .collect();
+    // deterministic order regardless of environment
+    overrides.sort();
+
+    for (key, raw_value) in overrides {
+        let path: Vec<String> = key[CONFIG_ENV_PREFIX.len()..]
+            .split(CONFIG_ENV_SEPARATOR)
+            .map(|segment| segment.to_lowercase())
+            .collect();
+        if path.iter().any(|segment| segment.is_empty()) {
+            return Err(override_error(&key, "empty path segment"));
+        }
+
+        let target = lookup(config, &path).map_err(|reason| override_error(&key, reason))?;
+        *target = override_value(target, raw_value);
+        info!("config value {} overridden from env", path.join("."));
+    }
+
+    Ok(())
+}
+
+/// merges every `PALANTIR_LABEL_*` variable into `labels`
+pub fn apply_env_labels<I>(labels: &mut BTreeMap<String, String>, vars: I)
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    for (key, value) in vars {
+        if !key.starts_with(EXTRA_LABEL_PREFIX) {
+            continue;
+        }
+        if !EXTRA_LABEL_REGEX.is_match(&key) || !EXTRA_LABEL_REGEX.is_match(&value) {
+            warn!("pair {}:{} is invalid", key, value);
+            continue;
+        }
+        let name = key[EXTRA_LABEL_PREFIX.len()..].to_lowercase();
+        if let Some(previous) = labels.insert(name.clone(), value) {
+            info!(
+                "extra label {} from env overrides config value {}",
+                name, previous
+            );
+        }
+    }
+}
+
+/// type of the overridden value decides how env value is read, see module docs
+fn override_value(current: &Value, raw_value: String) -> Value {
+    if current.is_string() {
+        return Value::String(raw_value);
+    }
+    match serde_yaml::from_str(&raw_value) {
+        Ok(value @ Value::Bool(_))
+        | Ok(value @ Value::Number(_))
+        | Ok(value @ Value::String(_)) => value,
+        _ => Value::String(raw_value),
+    }
+}
+
+fn override_error(variable: &str, reason: &str) -> ConfigurationError {
+    ConfigurationError::Override {
+        variable: variable.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+/// finds (or creates, for mappings) value by lowercased path
+fn lookup<'a>(mut node: &'a mut Value, path: &[String]) -> Result<&'a mut Value, &'static str> {
+    let mut segments = path.iter().peekable();
+
+    while let Some(segment) = segments.next() {
+        if node.is_null() {
+            *node = Value::Mapping(Mapping::new());
+        }
+        node = match node {
+            Value::Sequence(items) => {
+                let index: usize = segment.parse().map_err(|_| "sequence index expected")?;
+                let item = items.get_mut(index).ok_or("index out of range")?;
+                descend_into_variant(item, &mut segments)
+            }
+            Value::Mapping(mapping) => mapping_entry(mapping, segment),
+            _ => return Err("path goes through a scalar value"),
+        };
+    }
+
+    Ok(node)
+}
+
+/// enum values are mappings with a single key (`- UDP: {...}`)
+/// variant segment is optional in the path
+fn descend_into_variant<'a, 'b, I>(
+    item: &'a mut Value,
+    segments: &mut std::iter::Peekable<I>,
+) -> &'a mut Value
+where
+    I: Iterator<Item = &'b String>,
+{
+    let variant = match item {
+        Value::Mapping(mapping) if mapping.len() == 1 => {
+            mapping.iter().next().map(|(key, _)| key.clone())
+        }
+        _ => None,
+    };
+    match variant {
+        Some(variant) => {
+            if let (Some(next), Some(name)) = (segments.peek(), variant.as_str()) {
+                if name.to_lowercase() == **next {
+                    segments.next();
+                }
+            }
+            match item {
+                Value::Mapping(mapping) => mapping.get_mut(&variant).expect("variant is present"),
+                _ => unreachable!("checked above"),
+            }
+        }
+        None => item,
+    }
+}
+
+/// case-insensitive key lookup, missing keys are inserted lowercased
+fn mapping_entry<'a>(mapping: &'a mut Mapping, segment: &str) -> &'a mut Value {
+    let existing = mapping
+        .iter()
+        .map(|(key, _)| key)
+        .find(|key| key.as_str().map(|key| key.to_lowercase()) == Some(segment.to_string()))
+        .cloned();
+    let key = existing.unwrap_or_else(|| Value::String(segment.to_string()));
+    if !mapping.contains_key(&key) {
+        mapping.insert(key.clone(), Value::Null);
+    }
+    mapping.get_mut(&key).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::env::{apply_env_labels, apply_env_overrides};
+    use crate::config::parser::ConfigurationError;
+    use serde_yaml::Value;
+    use std::collections::BTreeMap;
+
+    const CONFIG: &str = "
+listeners:
+  - UDP:
+      port: 2746
+  - TCP:
+      port: 2747
+reporter:
+  vm_import_url: http://localhost:8428/api
+    ";
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_override_reporter() {
+        let mut config: Value = serde_yaml::from_str(CONFIG).unwrap();
+
+        apply_env_overrides(
+            &mut config,
+            vars(&[
+                ("PALANTIR__REPORTER__VM_IMPORT_URL", "http://vm:8428/api"),
+                ("PALANTIR_LABEL_POD", "ignored"),
+                ("HOME", "/root"),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            config["reporter"]["vm_import_url"],
+            Value::String("http://vm:8428/api".to_string())
+        );
+    }
+
+    #[test]
+    fn test_override_listeners() {
+        let mut config: Value = serde_yaml::from_str(CONFIG).unwrap();
+
+        apply_env_overrides(
+            &mut config,
+            vars(&[
+                ("PALANTIR__LISTENERS__0__PORT", "5545"),
+                ("PALANTIR__LISTENERS__1__TCP__ADDRESS", "0.0.0.0"),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(config["listeners"][0]["UDP"]["port"], Value::from(5545));
+        assert_eq!(
+            config["listeners"][1]["TCP"]["address"],
+            Value::String("0.0.0.0".to_string())
+        );
+    }
+
+    #[test]
+    fn test_override_types() {
+        let mut config: Value = serde_yaml::from_str(CONFIG).unwrap();
+
+        apply_env_overrides(
+            &mut config,
+            vars(&[
+                ("PALANTIR__REPORTER__VM_IMPORT_URL", "123456"),
+                ("PALANTIR__REPORTER__PERIOD_SECONDS", "30"),
+                ("PALANTIR__REPORTER__ALIGN_TO_PERIOD", "false"),
+                ("PALANTIR__REPORTER__HEADERS__ACCOUNTID", "\"42\""),
+                ("PALANTIR__REPORTER__HEADERS__TENANT", "team: a"),
+            ]),
+        )
+        .unwrap();
+
+        let reporter = &config["reporter"];
+        assert_eq!(
+            reporter["vm_import_url"],
+            Value::String("123456".to_string())
+        );
+        assert_eq!(reporter["period_seconds"], Value::from(30));
+        assert_eq!(reporter["align_to_period"], Value::Bool(false));
+        assert_eq!(
+            reporter["headers"]["accountid"],
+            Value::String("42".to_string())
+        );
+        assert_eq!(
+            reporter["headers"]["tenant"],
+            Value::String("team: a".to_string())
+        );
+    }
+
+    #[test]
+    fn test_override_empty_config() {
+        let mut config = Value::Null;
+
+        apply_env_overrides(
+            &mut config,
+            vars(&[("PALANTIR__REPORTER__VM_IMPORT_URL", "http://vm:8428/api")]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            config["reporter"]["vm_import_url"],
+            Value::String("http://vm:8428/api".to_string())
+        );
+    }
+
+    #[test]
+    fn test_override_missing_listener() {
+        let mut config: Value = serde_yaml::from_str(CONFIG).unwrap();
+
+        let result =
+            apply_env_overrides(&mut config, vars(&[("PALANTIR__LISTENERS__5__PORT", "1")]));
+
+        match result.err().unwrap() {
+            ConfigurationError::Override { variable, .. } => {
+                assert_eq!(variable, "PALANTIR__LISTENERS__5__PORT")
+            }
+            _ => panic!("wrong error"),
+        }
+    }
+
+    #[test]
+    fn test_env_labels() {
+        let mut labels = BTreeMap::new();
+        labels.insert("dc".to_string(), "eu-1".to_string());
+        labels.insert("pod".to_string(), "from-config".to_string());
+
+        apply_env_labels(
+            &mut labels,
+            vars(&[
+                ("PALANTIR_LABEL_POD", "web-1"),
+                ("PALANTIR_LABEL_BAD", "dot.inside"),
+                ("PALANTIR__REPORTER__PERIOD_SECONDS", "15"),
+            ]),
+        );
+
+        assert_eq!(labels.len(), 2);
+        assert_eq!(labels["dc"], "eu-1");
+        assert_eq!(labels["pod"], "web-1");
+    }
+}
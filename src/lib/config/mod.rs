@@ -1,3 +1,4 @@
 pub mod defs;
+pub mod env;
 pub mod parser;
 mod validator;
@@ -1,19 +1,52 @@
-use super::defs::Config;
+use super::defs::{Config, ExporterKind};
+use super::env::{apply_env_labels, apply_env_overrides};
 use super::validator::run_validation_chain;
+use crate::util::template::TemplateError;
+use crate::util::tls::TlsError;
 use serde_yaml;
-use serde_yaml::Error;
+use serde_yaml::{Error, Value};
 use std::convert::From;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use url::ParseError;
 
 #[derive(Debug)]
 pub enum ConfigurationError {
+    Read(std::io::Error),
     Parse(serde_yaml::Error),
-    Logic(LogicError),
+    Override { variable: String, reason: String },
+    Logic(Vec<FieldError>),
 }
 
-impl From<LogicError> for ConfigurationError {
-    fn from(err: LogicError) -> Self {
-        Self::Logic(err)
+impl fmt::Display for ConfigurationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(err) => write!(f, "unable to read config file: {}", err),
+            Self::Parse(err) => write!(f, "unable to parse config: {}", err),
+            Self::Override { variable, reason } => {
+                write!(f, "unable to apply {} env override: {}", variable, reason)
+            }
+            Self::Logic(errors) => {
+                write!(f, "invalid config:")?;
+                for err in errors {
+                    write!(f, "\n  {}", err)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for ConfigurationError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Read(err)
+    }
+}
+
+impl From<Vec<FieldError>> for ConfigurationError {
+    fn from(errors: Vec<FieldError>) -> Self {
+        Self::Logic(errors)
     }
 }
 
@@ -25,9 +58,106 @@ impl From<serde_yaml::Error> for ConfigurationError {
 
 #[derive(Debug)]
 pub enum LogicError {
-    PortUsedTwice(u16),
+    AddressUsedTwice(SocketAddr),
+    SocketPathUsedTwice(PathBuf),
     AtLeastOneListener,
+    OutOfRange { value: u64, min: u64, max: u64 },
     InvalidUri(ParseError),
+    UnsupportedScheme(String),
+    UnreachableHost(String),
+    InvalidLabelName(String),
+    ReservedLabelName(String),
+    InvalidHeader(String),
+    ReservedHeader(String),
+    Tls(TlsError),
+    TlsRequiresHttps,
+    SpoolPathUsedTwice(PathBuf),
+    ReservedTargetName(String),
+    NoDestination,
+    SeveralDestinations(Vec<&'static str>),
+    UnsupportedCompression(ExporterKind),
+    InvalidAddress(String),
+    InvalidPathTemplate(TemplateError),
+    FileDirUsedTwice(PathBuf),
+}
+
+/// logic error with the path of config field that caused it, e.g. `listeners[2].port`
+#[derive(Debug)]
+pub struct FieldError {
+    pub path: String,
+    pub error: LogicError,
+}
+
+impl FieldError {
+    pub fn new<P: Into<String>>(path: P, error: LogicError) -> Self {
+        Self {
+            path: path.into(),
+            error,
+        }
+    }
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.error)
+    }
+}
+
+impl fmt::Display for LogicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AddressUsedTwice(address) => write!(f, "address {} is used twice", address),
+            Self::SocketPathUsedTwice(path) => write!(f, "socket {:?} is used twice", path),
+            Self::AtLeastOneListener => write!(f, "at least one listener is required"),
+            Self::OutOfRange { value, min, max } => {
+                write!(f, "{} is out of range [{}, {}]", value, min, max)
+            }
+            Self::InvalidUri(err) => write!(f, "invalid url: {}", err),
+            Self::UnsupportedScheme(scheme) => {
+                write!(f, "unsupported scheme {}, expected http or https", scheme)
+            }
+            Self::UnreachableHost(url) => write!(f, "{} doesn't look like a reachable host", url),
+            Self::InvalidLabelName(name) => write!(f, "{} is not a valid label name", name),
+            Self::ReservedLabelName(name) => {
+                write!(f, "label {} is reserved for labels set by the agent", name)
+            }
+            Self::InvalidHeader(name) => write!(f, "invalid header {}", name),
+            Self::ReservedHeader(name) => write!(f, "header {} is set by the agent", name),
+            Self::Tls(err) => write!(f, "invalid TLS config, {}", err),
+            Self::TlsRequiresHttps => write!(f, "TLS is configured but url is not https"),
+            Self::SpoolPathUsedTwice(path) => write!(f, "spool {:?} is used twice", path),
+            Self::ReservedTargetName(name) => {
+                write!(f, "target name {} is reserved for `reporter` section", name)
+            }
+            Self::NoDestination => write!(
+                f,
+                "one of vm_import_url, remote_write_url, influx_write_url, graphite_address, otlp_url, file is required"
+            ),
+            Self::SeveralDestinations(fields) => {
+                write!(f, "only one of {} can be set", fields.join(", "))
+            }
+            Self::UnsupportedCompression(ExporterKind::RemoteWrite) => {
+                write!(f, "remote_write payloads are always snappy-compressed")
+            }
+            Self::UnsupportedCompression(ExporterKind::Influx) => {
+                write!(f, "InfluxDB accepts only gzip-compressed payloads")
+            }
+            Self::UnsupportedCompression(ExporterKind::Graphite) => {
+                write!(f, "Graphite plaintext protocol isn't compressed")
+            }
+            Self::UnsupportedCompression(ExporterKind::Otlp) => {
+                write!(f, "OTLP/HTTP accepts only gzip-compressed payloads")
+            }
+            Self::UnsupportedCompression(ExporterKind::VmImport | ExporterKind::File) => {
+                write!(f, "unsupported compression")
+            }
+            Self::InvalidAddress(address) => {
+                write!(f, "{} is not a valid host:port address", address)
+            }
+            Self::InvalidPathTemplate(err) => write!(f, "invalid path template, {}", err),
+            Self::FileDirUsedTwice(path) => write!(f, "directory {:?} is used twice", path),
+        }
+    }
 }
 
 impl From<ParseError> for LogicError {
@@ -36,28 +166,61 @@ impl From<ParseError> for LogicError {
     }
 }
 
-#[allow(dead_code)]
-fn parse_config<T: AsRef<str>>(raw_config: T) -> Result<Config, ConfigurationError> {
-    let config: Config = serde_yaml::from_str(raw_config.as_ref())?;
+impl From<TlsError> for LogicError {
+    fn from(err: TlsError) -> Self {
+        Self::Tls(err)
+    }
+}
+
+/// parses config with overrides from env `vars` applied, see `config::env`
+pub fn parse_config<T, I>(raw_config: T, vars: I) -> Result<Config, ConfigurationError>
+where
+    T: AsRef<str>,
+    I: IntoIterator<Item = (String, String)>,
+{
+    let vars: Vec<(String, String)> = vars.into_iter().collect();
+    let mut raw: Value = serde_yaml::from_str(raw_config.as_ref())?;
+    apply_env_overrides(&mut raw, vars.clone())?;
+    let mut config: Config = serde_yaml::from_value(raw)?;
+    apply_env_labels(&mut config.reporter.extra_labels, vars.clone());
+    for target in config.targets.values_mut() {
+        apply_env_labels(&mut target.extra_labels, vars.clone());
+    }
     run_validation_chain(&config)?;
 
     return Ok(config);
 }
 
+/// reads, parses and validates config file, `vars` are usually `std::env::vars()`
+pub fn load_config<P, I>(path: P, vars: I) -> Result<Config, ConfigurationError>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = (String, String)>,
+{
+    let raw_config = std::fs::read_to_string(path)?;
+    parse_config(raw_config, vars)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::config::defs::{Config, ListenerType, ReporterConfig, TCPConfig, UDPConfig};
-    use crate::config::parser::{parse_config, ConfigurationError};
+    use crate::config::defs::{
+        AuthConfig, Compression, Config, ListenerType, OtlpHistogram, ReporterConfig, RetryConfig,
+        Secret, SpoolConfig, TCPConfig, TargetFilter, UDPConfig, UnixStreamConfig,
+    };
+    use crate::config::parser::{load_config, parse_config, ConfigurationError};
+    use std::collections::BTreeMap;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::path::PathBuf;
 
     #[test]
     fn test_parse_invalid_yaml() {
         let yaml = "@not_a_yaml";
 
-        let result = parse_config(yaml).err().unwrap();
+        let result = parse_config(yaml, Vec::new()).err().unwrap();
 
         match result {
             ConfigurationError::Parse(_) => (),
-            ConfigurationError::Logic(_) => {
+            _ => {
                 panic!("Wrong error")
             }
         }
@@ -68,31 +231,234 @@ mod tests {
         let expected_config = Config {
             listeners: vec![
                 ListenerType::UDP(UDPConfig {
+                    address: IpAddr::from(Ipv4Addr::UNSPECIFIED),
                     port: 2746,
                     buffer_size: 4096,
                 }),
                 ListenerType::TCP(TCPConfig {
+                    address: IpAddr::from(Ipv4Addr::LOCALHOST),
                     port: 2747,
                     buffer_size: 4096,
+                    max_connections: 256,
+                    read_timeout_ms: 30_000,
+                }),
+                ListenerType::UnixStream(UnixStreamConfig {
+                    path: PathBuf::from("/var/run/palantir/agent.sock"),
+                    buffer_size: 4096,
+                    mode: Some(0o660),
                 }),
             ],
             reporter: ReporterConfig {
-                vm_import_url: "http://localhost:8428/api".to_string(),
+                vm_import_url: Some("http://localhost:8428/api".to_string()),
+                remote_write_url: None,
+                influx_write_url: None,
+                graphite_address: None,
+                graphite_path_template: "{realm}.{application}.{action_name}.{span}".to_string(),
+                otlp_url: None,
+                otlp_histogram: OtlpHistogram::Exponential,
+                file: None,
+                period_seconds: 15,
+                request_timeout_ms: None,
+                connect_timeout_ms: None,
+                align_to_period: true,
+                jitter_ms: 500,
+                extra_labels: vec![("dc".to_string(), "eu-1".to_string())]
+                    .into_iter()
+                    .collect(),
+                retry: RetryConfig::default(),
+                spool: Some(SpoolConfig {
+                    path: PathBuf::from("/var/lib/palantir/spool"),
+                    max_size_bytes: 256 * 1024 * 1024,
+                }),
+                compression: Compression::Zstd,
+                max_payload_bytes: Some(8 * 1024 * 1024),
+                max_parallel_pushes: 4,
+                auth: Some(AuthConfig::Bearer(Secret::File(PathBuf::from(
+                    "/run/secrets/vm-token",
+                )))),
+                headers: vec![("AccountID".to_string(), Secret::Value("42".to_string()))]
+                    .into_iter()
+                    .collect(),
+                tls: None,
+                filter: TargetFilter::default(),
             },
+            targets: BTreeMap::new(),
+            scrape: None,
         };
         let yaml = "
 ---
 listeners:
   - UDP:
+      address: 0.0.0.0
       port: 2746
       buffer_size: 4096
   - TCP:
       port: 2747
+  - UnixStream:
+      path: /var/run/palantir/agent.sock
+      mode: 0o660
 reporter:
   vm_import_url: http://localhost:8428/api
+  period_seconds: 15
+  align_to_period: true
+  jitter_ms: 500
+  extra_labels:
+    dc: eu-1
+  spool:
+    path: /var/lib/palantir/spool
+  compression: zstd
+  max_payload_bytes: 8388608
+  auth:
+    bearer:
+      file: /run/secrets/vm-token
+  headers:
+    AccountID: \"42\"
         ";
-        let result = parse_config(yaml).ok().unwrap();
+        let result = parse_config(yaml, Vec::new()).ok().unwrap();
 
         assert_eq!(result, expected_config)
     }
+
+    #[test]
+    fn test_parse_with_overrides() {
+        let yaml = "
+listeners:
+  - UDP:
+      port: 2746
+reporter:
+  vm_import_url: http://localhost:8428/api
+        ";
+        let vars = vec![
+            (
+                "PALANTIR__LISTENERS__0__PORT".to_string(),
+                "2747".to_string(),
+            ),
+            (
+                "PALANTIR__REPORTER__PERIOD_SECONDS".to_string(),
+                "30".to_string(),
+            ),
+        ];
+
+        let config = parse_config(yaml, vars).ok().unwrap();
+
+        assert_eq!(config.reporter.period_seconds, 30);
+        match &config.listeners[0] {
+            ListenerType::UDP(udp) => assert_eq!(udp.port, 2747),
+            _ => panic!("Wrong listener"),
+        }
+
+        let vars = vec![(
+            "PALANTIR__LISTENERS__0__ADDRESS".to_string(),
+            "not-an-address".to_string(),
+        )];
+
+        let result = parse_config(yaml, vars).err().unwrap();
+
+        match result {
+            ConfigurationError::Parse(_) => (),
+            _ => {
+                panic!("Wrong error")
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_header_secrets() {
+        let yaml = "
+listeners:
+  - UDP:
+      port: 2746
+reporter:
+  vm_import_url: http://localhost:8428/api
+  headers:
+    AccountID: tenant-42
+    X-Api-Key:
+      env: VM_API_KEY
+        ";
+
+        let config = parse_config(yaml, Vec::new()).ok().unwrap();
+
+        assert_eq!(
+            config.reporter.headers["AccountID"],
+            Secret::Value("tenant-42".to_string())
+        );
+        assert_eq!(
+            config.reporter.headers["X-Api-Key"],
+            Secret::Env("VM_API_KEY".to_string())
+        );
+        assert!(!format!("{:?}", config).contains("tenant-42"));
+        assert!(!serde_yaml::to_string(&config)
+            .unwrap()
+            .contains("tenant-42"));
+    }
+
+    #[test]
+    fn test_parse_reserved_env_label() {
+        let yaml = "
+listeners:
+  - UDP:
+      port: 2746
+reporter:
+  vm_import_url: http://localhost:8428/api
+        ";
+        let vars = vec![(
+            "PALANTIR_LABEL_PALANTIR_REALM".to_string(),
+            "prod".to_string(),
+        )];
+
+        let result = parse_config(yaml, vars).err().unwrap();
+
+        match result {
+            ConfigurationError::Logic(errors) => {
+                assert_eq!(errors[0].path, "reporter.extra_labels.palantir_realm")
+            }
+            _ => {
+                panic!("Wrong error")
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_targets() {
+        let yaml = "
+listeners:
+  - UDP:
+      port: 2746
+reporter:
+  vm_import_url: http://localhost:8428/api
+targets:
+  longterm:
+    vm_import_url: http://longterm:8428/api
+    period_seconds: 60
+    filter:
+      realms: [prod]
+        ";
+        let vars = vec![("PALANTIR_LABEL_DC".to_string(), "eu-1".to_string())];
+
+        let result = parse_config(yaml, vars).ok().unwrap();
+        let targets = result.all_targets();
+
+        assert_eq!(
+            targets.keys().collect::<Vec<_>>(),
+            vec!["default", "longterm"]
+        );
+        assert_eq!(targets["longterm"].period_seconds, 60);
+        assert_eq!(targets["longterm"].filter.realms, vec!["prod".to_string()]);
+        assert_eq!(targets["longterm"].extra_labels["dc"], "eu-1");
+        assert_eq!(targets["default"].extra_labels["dc"], "eu-1");
+    }
+
+    #[test]
+    fn test_load_missing_file() {
+        let result = load_config("/nonexistent/palantir.yaml", Vec::new())
+            .err()
+            .unwrap();
+
+        match result {
+            ConfigurationError::Read(_) => (),
+            _ => {
+                panic!("Wrong error")
+            }
+        }
+    }
 }
@@ -1,32 +1,332 @@
-use crate::config::defs::{Config, ListenerType};
-use crate::config::parser::LogicError;
-use std::collections::HashSet;
-use url::Url;
-
-/// checks that no port is used twice
-fn listeners_no_same_ports(listeners_config: &Vec<ListenerType>) -> Result<(), LogicError> {
-    let mut ports: HashSet<u16> = HashSet::new();
-
-    for listener in listeners_config {
-        match &listener {
-            ListenerType::UDP(cfg) => {
-                if ports.contains(&cfg.port) {
-                    return Err(LogicError::PortUsedTwice(cfg.port));
-                }
-                ports.insert(cfg.port);
-            }
-            ListenerType::TCP(cfg) => {
-                if ports.contains(&cfg.port) {
-                    return Err(LogicError::PortUsedTwice(cfg.port));
-                }
-                ports.insert(cfg.port);
+use crate::config::defs::{
+    AuthConfig, Compression, Config, ExporterKind, ListenerType, ReporterConfig, Secret, TlsConfig,
+};
+use crate::config::parser::{FieldError, LogicError};
+use crate::constants::{
+    DEFAULT_TARGET_NAME, LABEL_NAME_REGEX, RESERVED_LABEL_NAMES, RESERVED_LABEL_PREFIX,
+};
+use crate::util::template::PathTemplate;
+use crate::util::tls::client_config;
+use hyper::header::{HeaderName, HeaderValue, AUTHORIZATION};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use url::{Host, Url};
+
+/// anything smaller can't hold a meaningful request
+const MIN_BUFFER_SIZE: u64 = 64;
+/// max UDP payload over IPv4
+const MAX_UDP_BUFFER_SIZE: u64 = 65507;
+const MAX_BUFFER_SIZE: u64 = u16::MAX as u64;
+const MAX_HTTP_BODY_SIZE: u64 = 64 * 1024 * 1024;
+const MAX_TCP_CONNECTIONS: u64 = u16::MAX as u64;
+const MAX_READ_TIMEOUT_MS: u64 = 3600 * 1000;
+const MAX_REPORT_PERIOD_SECONDS: u64 = 3600;
+const MAX_PUSH_ATTEMPTS: u64 = 100;
+const MIN_SPOOL_SIZE: u64 = 1024 * 1024;
+const MIN_PAYLOAD_SIZE: u64 = 64 * 1024;
+const MAX_PARALLEL_PUSHES: u64 = 64;
+const MIN_FILE_SIZE: u64 = 64 * 1024;
+
+/// transport protocol, listeners of different transports can share address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Transport {
+    Udp,
+    Tcp,
+}
+
+impl Transport {
+    /// None for listeners not bound to network address
+    fn of(listener: &ListenerType) -> Option<Self> {
+        match listener {
+            ListenerType::UDP(_) => Some(Transport::Udp),
+            ListenerType::TCP(_) | ListenerType::HTTP(_) => Some(Transport::Tcp),
+            ListenerType::UnixDatagram(_) | ListenerType::UnixStream(_) => None,
+        }
+    }
+}
+
+/// same port on the same address, or on the wildcard address covering it
+fn addresses_overlap(first: &SocketAddr, second: &SocketAddr) -> bool {
+    if first.port() != second.port() {
+        return false;
+    }
+    let (a, b) = (first.ip(), second.ip());
+    if a == b {
+        return true;
+    }
+    match (a, b) {
+        // `::` is dual-stack and also covers every IPv4 address
+        (IpAddr::V6(ip), _) | (_, IpAddr::V6(ip)) if ip.is_unspecified() => true,
+        (IpAddr::V4(a), IpAddr::V4(b)) => a.is_unspecified() || b.is_unspecified(),
+        _ => false,
+    }
+}
+
+fn listener_path(index: usize, field: &str) -> String {
+    format!("listeners[{}].{}", index, field)
+}
+
+/// checks that no (address, port, protocol) is used twice
+fn listeners_no_same_addresses(listeners_config: &[ListenerType], errors: &mut Vec<FieldError>) {
+    let mut bound: Vec<(Transport, SocketAddr)> = Vec::new();
+
+    for (index, listener) in listeners_config.iter().enumerate() {
+        let (transport, address) = match (Transport::of(listener), listener.socket_address()) {
+            (Some(transport), Some(address)) => (transport, address),
+            _ => continue,
+        };
+        let used_twice = bound.iter().any(|(used_transport, used_address)| {
+            *used_transport == transport && addresses_overlap(used_address, &address)
+        });
+        if used_twice {
+            errors.push(FieldError::new(
+                listener_path(index, "port"),
+                LogicError::AddressUsedTwice(address),
+            ));
+        }
+        bound.push((transport, address));
+    }
+}
+
+/// scrape endpoint is a TCP listener too
+fn scrape_address_is_free(config: &Config, errors: &mut Vec<FieldError>) {
+    let scrape = match &config.scrape {
+        Some(scrape) => SocketAddr::new(scrape.address, scrape.port),
+        None => return,
+    };
+    let used = config.listeners.iter().any(|listener| {
+        match (Transport::of(listener), listener.socket_address()) {
+            (Some(Transport::Tcp), Some(address)) => addresses_overlap(&address, &scrape),
+            _ => false,
+        }
+    });
+    if used {
+        errors.push(FieldError::new(
+            "scrape.port",
+            LogicError::AddressUsedTwice(scrape),
+        ));
+    }
+}
+
+/// checks that no socket file is used twice, whatever the socket type
+fn listeners_no_same_socket_paths(listeners_config: &[ListenerType], errors: &mut Vec<FieldError>) {
+    let mut paths: HashMap<&PathBuf, usize> = HashMap::new();
+
+    for (index, listener) in listeners_config.iter().enumerate() {
+        if let Some(path) = listener.socket_path() {
+            if paths.insert(path, index).is_some() {
+                errors.push(FieldError::new(
+                    listener_path(index, "path"),
+                    LogicError::SocketPathUsedTwice(path.clone()),
+                ));
             }
         }
     }
+}
 
+fn check_range(value: u64, min: u64, max: u64) -> Result<(), LogicError> {
+    if value < min || value > max {
+        return Err(LogicError::OutOfRange { value, min, max });
+    }
     Ok(())
 }
 
+/// checks that buffers can hold a request and fit into a single datagram
+fn listeners_buffer_sizes(listeners_config: &[ListenerType], errors: &mut Vec<FieldError>) {
+    for (index, listener) in listeners_config.iter().enumerate() {
+        let (field, result) = match listener {
+            ListenerType::UDP(cfg) => (
+                "buffer_size",
+                check_range(cfg.buffer_size as u64, MIN_BUFFER_SIZE, MAX_UDP_BUFFER_SIZE),
+            ),
+            ListenerType::TCP(cfg) => (
+                "buffer_size",
+                check_range(cfg.buffer_size as u64, MIN_BUFFER_SIZE, MAX_BUFFER_SIZE),
+            ),
+            ListenerType::UnixDatagram(cfg) => (
+                "buffer_size",
+                check_range(cfg.buffer_size as u64, MIN_BUFFER_SIZE, MAX_BUFFER_SIZE),
+            ),
+            ListenerType::UnixStream(cfg) => (
+                "buffer_size",
+                check_range(cfg.buffer_size as u64, MIN_BUFFER_SIZE, MAX_BUFFER_SIZE),
+            ),
+            ListenerType::HTTP(cfg) => (
+                "max_body_size",
+                check_range(
+                    cfg.max_body_size as u64,
+                    MIN_BUFFER_SIZE,
+                    MAX_HTTP_BODY_SIZE,
+                ),
+            ),
+        };
+        if let Err(err) = result {
+            errors.push(FieldError::new(listener_path(index, field), err));
+        }
+    }
+}
+
+/// zero would close every TCP connection right after accept
+fn listeners_connection_limits(listeners_config: &[ListenerType], errors: &mut Vec<FieldError>) {
+    for (index, listener) in listeners_config.iter().enumerate() {
+        let cfg = match listener {
+            ListenerType::TCP(cfg) => cfg,
+            _ => continue,
+        };
+        if let Err(err) = check_range(cfg.max_connections as u64, 1, MAX_TCP_CONNECTIONS) {
+            errors.push(FieldError::new(
+                listener_path(index, "max_connections"),
+                err,
+            ));
+        }
+        if let Err(err) = check_range(cfg.read_timeout_ms, 1, MAX_READ_TIMEOUT_MS) {
+            errors.push(FieldError::new(
+                listener_path(index, "read_timeout_ms"),
+                err,
+            ));
+        }
+    }
+}
+
+/// checks that report is done before the next one starts and jitter doesn't skip a report
+fn reporter_schedule(prefix: &str, reporter: &ReporterConfig, errors: &mut Vec<FieldError>) {
+    let mut check = |field: &str, result: Result<(), LogicError>| {
+        if let Err(err) = result {
+            errors.push(FieldError::new(format!("{}.{}", prefix, field), err));
+        }
+    };
+    let period_ms = reporter.period_seconds.saturating_mul(1000);
+    let period = check_range(reporter.period_seconds, 1, MAX_REPORT_PERIOD_SECONDS);
+    let period_valid = period.is_ok();
+    check("period_seconds", period);
+
+    // limits derived from an invalid period would only repeat its error
+    if let (true, Some(request_timeout_ms)) = (period_valid, reporter.request_timeout_ms) {
+        check(
+            "request_timeout_ms",
+            check_range(request_timeout_ms, 1, period_ms),
+        );
+    }
+    if let Some(connect_timeout_ms) = reporter.connect_timeout_ms {
+        let request_timeout_ms = reporter.request_timeout().as_millis() as u64;
+        check(
+            "connect_timeout_ms",
+            check_range(connect_timeout_ms, 1, request_timeout_ms.max(1)),
+        );
+    }
+    if period_valid {
+        check(
+            "jitter_ms",
+            check_range(reporter.jitter_ms, 0, period_ms - 1),
+        );
+    }
+}
+
+/// checks that retries, pushes and spool have sane limits
+fn reporter_push_limits(prefix: &str, reporter: &ReporterConfig, errors: &mut Vec<FieldError>) {
+    let retry = &reporter.retry;
+    let mut check = |field: &str, result: Result<(), LogicError>| {
+        if let Err(err) = result {
+            errors.push(FieldError::new(format!("{}.{}", prefix, field), err));
+        }
+    };
+
+    check(
+        "retry.max_attempts",
+        check_range(retry.max_attempts as u64, 1, MAX_PUSH_ATTEMPTS),
+    );
+    check(
+        "retry.initial_backoff_ms",
+        check_range(retry.initial_backoff_ms, 1, retry.max_backoff_ms.max(1)),
+    );
+    if let Some(max_payload_bytes) = reporter.max_payload_bytes {
+        check(
+            "max_payload_bytes",
+            check_range(max_payload_bytes, MIN_PAYLOAD_SIZE, u64::MAX),
+        );
+    }
+    check(
+        "max_parallel_pushes",
+        check_range(reporter.max_parallel_pushes as u64, 1, MAX_PARALLEL_PUSHES),
+    );
+    if let Some(file) = &reporter.file {
+        check(
+            "file.max_file_bytes",
+            check_range(file.max_file_bytes, MIN_FILE_SIZE, u64::MAX),
+        );
+        check("file.max_files", check_range(file.max_files, 1, u64::MAX));
+    }
+    if let Some(spool) = &reporter.spool {
+        check(
+            "spool.max_size_bytes",
+            check_range(spool.max_size_bytes, MIN_SPOOL_SIZE, u64::MAX),
+        );
+    }
+}
+
+/// checks that extra labels are valid prometheus label names and don't clash with built-in ones
+fn reporter_extra_labels(prefix: &str, reporter: &ReporterConfig, errors: &mut Vec<FieldError>) {
+    for name in reporter.extra_labels.keys() {
+        let path = format!("{}.extra_labels.{}", prefix, name);
+        if !LABEL_NAME_REGEX.is_match(name) {
+            errors.push(FieldError::new(
+                path,
+                LogicError::InvalidLabelName(name.clone()),
+            ));
+        } else if name.starts_with(RESERVED_LABEL_PREFIX)
+            || RESERVED_LABEL_NAMES.contains(&name.as_str())
+        {
+            errors.push(FieldError::new(
+                path,
+                LogicError::ReservedLabelName(name.clone()),
+            ));
+        }
+    }
+}
+
+/// headers set by the agent itself, `Authorization` comes from `auth`
+const RESERVED_HEADERS: [&str; 5] = [
+    "authorization",
+    "content-encoding",
+    "content-length",
+    "host",
+    "transfer-encoding",
+];
+
+/// checks that custom headers are valid and don't clash with the ones agent sets
+fn reporter_headers(prefix: &str, reporter: &ReporterConfig, errors: &mut Vec<FieldError>) {
+    for (name, value) in reporter.headers.iter() {
+        let path = format!("{}.headers.{}", prefix, name);
+        // values of other secrets are only known at push time
+        let invalid_value = match value {
+            Secret::Value(value) => HeaderValue::from_str(value).is_err(),
+            Secret::File(_) | Secret::Env(_) => false,
+        };
+        if HeaderName::from_bytes(name.as_bytes()).is_err() || invalid_value {
+            errors.push(FieldError::new(
+                path,
+                LogicError::InvalidHeader(name.clone()),
+            ));
+        } else if RESERVED_HEADERS.contains(&name.to_lowercase().as_str()) {
+            errors.push(FieldError::new(
+                path,
+                LogicError::ReservedHeader(name.clone()),
+            ));
+        }
+    }
+
+    if let Some(AuthConfig::Basic { username, .. }) = &reporter.auth {
+        // `:` separates username from password in basic auth
+        if username.is_empty() || username.contains(':') {
+            errors.push(FieldError::new(
+                format!("{}.auth.basic.username", prefix),
+                LogicError::InvalidHeader(AUTHORIZATION.to_string()),
+            ));
+        }
+    }
+}
+
 /// checks that there is at least one configured listener
 fn listeners_at_least_one(listeners_config: &Vec<ListenerType>) -> Result<(), LogicError> {
     if listeners_config.is_empty() {
@@ -35,38 +335,227 @@ fn listeners_at_least_one(listeners_config: &Vec<ListenerType>) -> Result<(), Lo
     Ok(())
 }
 
-fn vm_import_url_is_valid(url: &str) -> Result<(), LogicError> {
-    Url::parse(url)?;
-    Ok(())
+/// wildcard, multicast and broadcast addresses parse fine but can't be pushed to
+fn host_is_reachable(host: &Host<&str>) -> bool {
+    match host {
+        Host::Domain(domain) => !domain.is_empty(),
+        Host::Ipv4(ip) => !(ip.is_unspecified() || ip.is_multicast() || ip.is_broadcast()),
+        Host::Ipv6(ip) => !(ip.is_unspecified() || ip.is_multicast()),
+    }
 }
 
-#[allow(dead_code)]
-pub fn run_validation_chain(config: &Config) -> Result<(), LogicError> {
-    listeners_at_least_one(&config.listeners)?;
-    listeners_no_same_ports(&config.listeners)?;
-    vm_import_url_is_valid(&config.reporter.vm_import_url)?;
+/// https needs loadable TLS files, and TLS files make no sense for plain http
+fn push_url_is_valid(url: &str, tls: Option<&TlsConfig>) -> Result<(), LogicError> {
+    let url = Url::parse(url)?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(LogicError::UnsupportedScheme(url.scheme().to_string()));
+    }
+    match url.host() {
+        Some(host) if host_is_reachable(&host) => (),
+        _ => return Err(LogicError::UnreachableHost(url.to_string())),
+    }
+    if url.port() == Some(0) {
+        return Err(LogicError::UnreachableHost(url.to_string()));
+    }
+    if url.scheme() == "https" {
+        client_config(tls)?;
+    } else if tls.is_some() {
+        return Err(LogicError::TlsRequiresHttps);
+    }
+    Ok(())
+}
 
+/// `host:port` of plaintext TCP targets, TLS isn't supported there
+fn tcp_address_is_valid(address: &str, tls: Option<&TlsConfig>) -> Result<(), LogicError> {
+    let invalid = || LogicError::InvalidAddress(address.to_string());
+    let url = Url::parse(&format!("tcp://{}", address)).map_err(|_| invalid())?;
+    let host_port_only = url.username().is_empty()
+        && url.path().is_empty()
+        && url.query().is_none()
+        && url.fragment().is_none();
+    if !host_port_only || url.port().unwrap_or(0) == 0 {
+        return Err(invalid());
+    }
+    match url.host() {
+        Some(host) if host_is_reachable(&host) => (),
+        _ => return Err(LogicError::UnreachableHost(address.to_string())),
+    }
+    if tls.is_some() {
+        return Err(LogicError::TlsRequiresHttps);
+    }
     Ok(())
 }
 
+/// remote_write protocol mandates snappy, InfluxDB and OTLP receivers don't decode zstd
+fn compression_is_supported(kind: ExporterKind, compression: Compression) -> bool {
+    match kind {
+        ExporterKind::VmImport | ExporterKind::File => true,
+        ExporterKind::RemoteWrite | ExporterKind::Graphite => compression == Compression::None,
+        ExporterKind::Influx | ExporterKind::Otlp => compression != Compression::Zstd,
+    }
+}
+
+fn reporter_is_valid(prefix: &str, reporter: &ReporterConfig, errors: &mut Vec<FieldError>) {
+    match reporter.destinations().as_slice() {
+        [] => errors.push(FieldError::new(prefix, LogicError::NoDestination)),
+        [(kind, field, url)] => {
+            let valid = match kind {
+                ExporterKind::Graphite => tcp_address_is_valid(url, reporter.tls.as_ref()),
+                ExporterKind::File => Ok(()),
+                _ => push_url_is_valid(url, reporter.tls.as_ref()),
+            };
+            if let Err(err) = valid {
+                let field = match err {
+                    LogicError::Tls(_) => "tls",
+                    _ => field,
+                };
+                errors.push(FieldError::new(format!("{}.{}", prefix, field), err));
+            }
+            if *kind == ExporterKind::Graphite {
+                if let Err(err) = PathTemplate::parse(&reporter.graphite_path_template) {
+                    errors.push(FieldError::new(
+                        format!("{}.graphite_path_template", prefix),
+                        LogicError::InvalidPathTemplate(err),
+                    ));
+                }
+            }
+            if !compression_is_supported(*kind, reporter.compression) {
+                errors.push(FieldError::new(
+                    format!("{}.compression", prefix),
+                    LogicError::UnsupportedCompression(*kind),
+                ));
+            }
+        }
+        several => errors.push(FieldError::new(
+            prefix,
+            LogicError::SeveralDestinations(several.iter().map(|(_, field, _)| *field).collect()),
+        )),
+    }
+    reporter_schedule(prefix, reporter, errors);
+    reporter_extra_labels(prefix, reporter, errors);
+    reporter_push_limits(prefix, reporter, errors);
+    reporter_headers(prefix, reporter, errors);
+}
+
+/// config path and section of every target, `reporter` goes first
+fn target_sections(config: &Config) -> Vec<(String, &ReporterConfig)> {
+    let mut sections = vec![("reporter".to_string(), &config.reporter)];
+    sections.extend(
+        config
+            .targets
+            .iter()
+            .map(|(name, target)| (format!("targets.{}", name), target)),
+    );
+    sections
+}
+
+/// targets sharing a spool would replay each other's payloads
+fn targets_no_same_spool_paths(
+    sections: &[(String, &ReporterConfig)],
+    errors: &mut Vec<FieldError>,
+) {
+    let mut seen = HashSet::new();
+    for (prefix, reporter) in sections {
+        if let Some(spool) = &reporter.spool {
+            if !seen.insert(&spool.path) {
+                errors.push(FieldError::new(
+                    format!("{}.spool.path", prefix),
+                    LogicError::SpoolPathUsedTwice(spool.path.clone()),
+                ));
+            }
+        }
+    }
+}
+
+/// file targets sharing a directory would remove each other's files
+fn targets_no_same_file_dirs(sections: &[(String, &ReporterConfig)], errors: &mut Vec<FieldError>) {
+    let mut seen = HashSet::new();
+    for (prefix, reporter) in sections {
+        if let Some(file) = &reporter.file {
+            if !seen.insert(&file.dir) {
+                errors.push(FieldError::new(
+                    format!("{}.file.dir", prefix),
+                    LogicError::FileDirUsedTwice(file.dir.clone()),
+                ));
+            }
+        }
+    }
+}
+
+/// runs every check, Err contains all found problems
+pub fn run_validation_chain(config: &Config) -> Result<(), Vec<FieldError>> {
+    let mut errors = Vec::new();
+
+    if let Err(err) = listeners_at_least_one(&config.listeners) {
+        errors.push(FieldError::new("listeners", err));
+    }
+    listeners_no_same_addresses(&config.listeners, &mut errors);
+    listeners_no_same_socket_paths(&config.listeners, &mut errors);
+    listeners_buffer_sizes(&config.listeners, &mut errors);
+    listeners_connection_limits(&config.listeners, &mut errors);
+    scrape_address_is_free(config, &mut errors);
+    let sections = target_sections(config);
+    for (prefix, reporter) in sections.iter() {
+        reporter_is_valid(prefix, reporter, &mut errors);
+    }
+    targets_no_same_spool_paths(&sections, &mut errors);
+    targets_no_same_file_dirs(&sections, &mut errors);
+    if config.targets.contains_key(DEFAULT_TARGET_NAME) {
+        errors.push(FieldError::new(
+            format!("targets.{}", DEFAULT_TARGET_NAME),
+            LogicError::ReservedTargetName(DEFAULT_TARGET_NAME.to_string()),
+        ));
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::config::defs::{Config, ListenerType, ReporterConfig, TCPConfig, UDPConfig};
+    use crate::config::defs::{
+        AuthConfig, Compression, Config, HTTPConfig, ListenerType, ReporterConfig, ScrapeConfig,
+        Secret, SpoolConfig, TCPConfig, TlsConfig, UDPConfig, UnixDatagramConfig, UnixStreamConfig,
+    };
     use crate::config::parser::LogicError;
-    use crate::config::validator::{run_validation_chain, vm_import_url_is_valid};
+    use crate::config::validator::{addresses_overlap, push_url_is_valid, run_validation_chain};
+    use std::collections::BTreeMap;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn reporter(vm_import_url: &str) -> ReporterConfig {
+        serde_yaml::from_str(&format!("vm_import_url: \"{}\"", vm_import_url)).unwrap()
+    }
+
+    /// single UDP listener, nothing else configured
+    fn config_with(reporter: ReporterConfig) -> Config {
+        Config {
+            targets: BTreeMap::new(),
+            scrape: None,
+            listeners: vec![ListenerType::UDP(UDPConfig {
+                address: IpAddr::from(Ipv4Addr::LOCALHOST),
+                port: 2746,
+                buffer_size: 4096,
+            })],
+            reporter,
+        }
+    }
 
     #[test]
     fn test_no_listeners_invalid() {
         let config = Config {
             listeners: vec![],
-            reporter: ReporterConfig {
-                vm_import_url: "".to_string(),
-            },
+            ..config_with(reporter(""))
         };
 
         let result = run_validation_chain(&config).err().unwrap();
 
-        match result {
+        assert_eq!(result[0].path, "listeners");
+        match result[0].error {
             LogicError::AtLeastOneListener => (),
             _ => {
                 panic!("wrong error")
@@ -75,28 +564,85 @@ mod tests {
     }
 
     #[test]
-    fn test_port_used_twice() {
+    fn test_address_used_twice() {
         let config = Config {
             listeners: vec![
                 ListenerType::UDP(UDPConfig {
+                    address: IpAddr::from(Ipv4Addr::LOCALHOST),
+                    port: 2746,
+                    buffer_size: 4096,
+                }),
+                ListenerType::UDP(UDPConfig {
+                    address: IpAddr::from(Ipv4Addr::LOCALHOST),
+                    port: 2746,
+                    buffer_size: 4096,
+                }),
+            ],
+            ..config_with(reporter("http://localhost:8428/api/v1/import/prometheus"))
+        };
+
+        let result = run_validation_chain(&config).err().unwrap();
+
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].path, "listeners[1].port");
+        match result[0].error {
+            LogicError::AddressUsedTwice(address) => {
+                assert_eq!(address, "127.0.0.1:2746".parse().unwrap())
+            }
+            _ => {
+                panic!("wrong error")
+            }
+        }
+    }
+
+    #[test]
+    fn test_same_port_different_protocols() {
+        let config = Config {
+            listeners: vec![
+                ListenerType::UDP(UDPConfig {
+                    address: IpAddr::from(Ipv4Addr::LOCALHOST),
                     port: 2746,
                     buffer_size: 4096,
                 }),
                 ListenerType::TCP(TCPConfig {
+                    address: IpAddr::from(Ipv4Addr::LOCALHOST),
                     port: 2746,
                     buffer_size: 4096,
+                    max_connections: 256,
+                    read_timeout_ms: 30_000,
                 }),
             ],
-            reporter: ReporterConfig {
-                vm_import_url: "".to_string(),
-            },
+            ..config_with(reporter("http://localhost:8428/api/v1/import/prometheus"))
+        };
+
+        run_validation_chain(&config).unwrap();
+    }
+
+    #[test]
+    fn test_socket_path_used_twice() {
+        let config = Config {
+            listeners: vec![
+                ListenerType::UnixDatagram(UnixDatagramConfig {
+                    path: PathBuf::from("/run/palantir.sock"),
+                    buffer_size: 4096,
+                    mode: None,
+                }),
+                ListenerType::UnixStream(UnixStreamConfig {
+                    path: PathBuf::from("/run/palantir.sock"),
+                    buffer_size: 4096,
+                    mode: None,
+                }),
+            ],
+            ..config_with(reporter("http://localhost:8428/api/v1/import/prometheus"))
         };
 
         let result = run_validation_chain(&config).err().unwrap();
 
-        match result {
-            LogicError::PortUsedTwice(port) => {
-                assert_eq!(port, 2746)
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].path, "listeners[1].path");
+        match &result[0].error {
+            LogicError::SocketPathUsedTwice(path) => {
+                assert_eq!(*path, PathBuf::from("/run/palantir.sock"))
             }
             _ => {
                 panic!("wrong error")
@@ -104,36 +650,479 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_addresses_overlap() {
+        let cases = vec![
+            ("127.0.0.1:2746", "127.0.0.1:2746", true),
+            ("127.0.0.1:2746", "127.0.0.1:2747", false),
+            ("127.0.0.1:2746", "10.0.0.1:2746", false),
+            ("0.0.0.0:2746", "10.0.0.1:2746", true),
+            ("[::]:2746", "10.0.0.1:2746", true),
+            ("[::1]:2746", "127.0.0.1:2746", false),
+            ("[::1]:2746", "[::]:2746", true),
+            ("0.0.0.0:2746", "[::1]:2746", false),
+        ];
+
+        for (first, second, expected) in cases {
+            let first: SocketAddr = first.parse().unwrap();
+            let second: SocketAddr = second.parse().unwrap();
+            assert_eq!(addresses_overlap(&first, &second), expected);
+            assert_eq!(addresses_overlap(&second, &first), expected);
+        }
+    }
+
     #[test]
     fn test_ok() {
         let config = Config {
             listeners: vec![
                 ListenerType::UDP(UDPConfig {
+                    address: IpAddr::from(Ipv4Addr::LOCALHOST),
                     port: 2746,
                     buffer_size: 4096,
                 }),
                 ListenerType::TCP(TCPConfig {
+                    address: IpAddr::from(Ipv4Addr::LOCALHOST),
                     port: 2747,
                     buffer_size: 4096,
+                    max_connections: 256,
+                    read_timeout_ms: 30_000,
                 }),
             ],
-            reporter: ReporterConfig {
-                vm_import_url: "http://localhost:8428/api/v1/import/prometheus".to_string(),
-            },
+            ..config_with(reporter("http://localhost:8428/api/v1/import/prometheus"))
         };
 
-        let _result = run_validation_chain(&config).ok().unwrap();
+        run_validation_chain(&config).unwrap();
     }
 
     #[test]
     fn test_invalid_url() {
         let invalid_url = "http://";
 
-        match vm_import_url_is_valid(invalid_url).unwrap_err() {
+        match push_url_is_valid(invalid_url, None).unwrap_err() {
             LogicError::InvalidUri(_) => (),
             _ => {
                 panic!("wrong match branch")
             }
         }
     }
+
+    #[test]
+    fn test_url_scheme_and_host() {
+        let cases = vec![
+            "ftp://localhost:8428/api",
+            "http://0.0.0.0:8428/api",
+            "http://224.0.0.1:8428/api",
+            "http://[::]:8428/api",
+            "http://localhost:0/api",
+            "unix:/run/vm.sock",
+        ];
+
+        for url in cases {
+            match push_url_is_valid(url, None).unwrap_err() {
+                LogicError::UnsupportedScheme(_) | LogicError::UnreachableHost(_) => (),
+                err => panic!("wrong error for {}: {:?}", url, err),
+            }
+        }
+        push_url_is_valid("https://vm.example.com/api/v1/import/prometheus", None).unwrap();
+    }
+
+    #[test]
+    fn test_all_errors_collected() {
+        let config = Config {
+            listeners: vec![
+                ListenerType::UDP(UDPConfig {
+                    address: IpAddr::from(Ipv4Addr::LOCALHOST),
+                    port: 2746,
+                    buffer_size: 65535,
+                }),
+                ListenerType::TCP(TCPConfig {
+                    address: IpAddr::from(Ipv4Addr::LOCALHOST),
+                    port: 2746,
+                    buffer_size: 8,
+                    max_connections: 0,
+                    read_timeout_ms: 30_000,
+                }),
+                ListenerType::HTTP(HTTPConfig {
+                    address: IpAddr::from(Ipv4Addr::UNSPECIFIED),
+                    port: 2746,
+                    max_body_size: 1024,
+                }),
+            ],
+            ..config_with(reporter("ftp://localhost/"))
+        };
+
+        let result = run_validation_chain(&config).err().unwrap();
+        let paths: Vec<&str> = result.iter().map(|err| err.path.as_str()).collect();
+
+        assert_eq!(
+            paths,
+            vec![
+                "listeners[2].port",
+                "listeners[0].buffer_size",
+                "listeners[1].buffer_size",
+                "listeners[1].max_connections",
+                "reporter.vm_import_url",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reporter_schedule() {
+        let mut reporter = reporter("http://localhost:8428/api/v1/import/prometheus");
+        reporter.period_seconds = 15;
+        reporter.request_timeout_ms = Some(20_000);
+        reporter.connect_timeout_ms = Some(0);
+        reporter.jitter_ms = 15_000;
+        let config = config_with(reporter);
+
+        let result = run_validation_chain(&config).err().unwrap();
+        let paths: Vec<&str> = result.iter().map(|err| err.path.as_str()).collect();
+
+        assert_eq!(
+            paths,
+            vec![
+                "reporter.request_timeout_ms",
+                "reporter.connect_timeout_ms",
+                "reporter.jitter_ms",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reporter_default_timeouts_fit_period() {
+        let mut reporter = reporter("http://localhost:8428/api/v1/import/prometheus");
+        reporter.period_seconds = 1;
+        let config = config_with(reporter);
+
+        assert!(run_validation_chain(&config).is_ok());
+        assert_eq!(
+            config.reporter.request_timeout(),
+            Duration::from_millis(1000)
+        );
+        assert_eq!(
+            config.reporter.connect_timeout(),
+            Duration::from_millis(1000)
+        );
+    }
+
+    #[test]
+    fn test_reporter_zero_period() {
+        let mut reporter = reporter("http://localhost:8428/api/v1/import/prometheus");
+        reporter.period_seconds = 0;
+        reporter.request_timeout_ms = Some(5000);
+        let config = config_with(reporter);
+
+        let result = run_validation_chain(&config).err().unwrap();
+        let paths: Vec<&str> = result.iter().map(|err| err.path.as_str()).collect();
+
+        assert_eq!(paths, vec!["reporter.period_seconds"]);
+    }
+
+    #[test]
+    fn test_reporter_period_overflow() {
+        let mut reporter = reporter("http://localhost:8428/api/v1/import/prometheus");
+        reporter.period_seconds = u64::MAX;
+        let config = config_with(reporter);
+
+        let result = run_validation_chain(&config).err().unwrap();
+        let paths: Vec<&str> = result.iter().map(|err| err.path.as_str()).collect();
+
+        assert_eq!(paths, vec!["reporter.period_seconds"]);
+    }
+
+    #[test]
+    fn test_extra_labels() {
+        let mut reporter = reporter("http://localhost:8428/api/v1/import/prometheus");
+        for name in &["pod", "palantir_realm", "vmrange", "with-dash"] {
+            reporter
+                .extra_labels
+                .insert(name.to_string(), "value".to_string());
+        }
+        let config = config_with(reporter);
+
+        let result = run_validation_chain(&config).err().unwrap();
+
+        assert_eq!(result.len(), 3);
+        match &result[0].error {
+            LogicError::ReservedLabelName(name) => assert_eq!(name, "palantir_realm"),
+            err => panic!("wrong error {:?}", err),
+        }
+        match &result[1].error {
+            LogicError::ReservedLabelName(name) => assert_eq!(name, "vmrange"),
+            err => panic!("wrong error {:?}", err),
+        }
+        match &result[2].error {
+            LogicError::InvalidLabelName(name) => assert_eq!(name, "with-dash"),
+            err => panic!("wrong error {:?}", err),
+        }
+        assert_eq!(result[2].path, "reporter.extra_labels.with-dash");
+    }
+
+    #[test]
+    fn test_push_limits() {
+        let mut reporter = reporter("http://localhost:8428/api/v1/import/prometheus");
+        reporter.retry.max_attempts = 0;
+        reporter.retry.initial_backoff_ms = 10_000;
+        reporter.max_payload_bytes = Some(1024);
+        reporter.max_parallel_pushes = 0;
+        reporter.spool = Some(SpoolConfig {
+            path: PathBuf::from("/var/lib/palantir/spool"),
+            max_size_bytes: 1024,
+        });
+        let config = config_with(reporter);
+
+        let result = run_validation_chain(&config).err().unwrap();
+        let paths: Vec<&str> = result.iter().map(|err| err.path.as_str()).collect();
+
+        assert_eq!(
+            paths,
+            vec![
+                "reporter.retry.max_attempts",
+                "reporter.retry.initial_backoff_ms",
+                "reporter.max_payload_bytes",
+                "reporter.max_parallel_pushes",
+                "reporter.spool.max_size_bytes",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_headers() {
+        let mut reporter = reporter("http://localhost:8428/api/v1/import/prometheus");
+        for (name, value) in &[
+            ("AccountID", "42"),
+            ("Content-Encoding", "gzip"),
+            ("bad header", "x"),
+            ("X-Bad-Value", "line\nbreak"),
+        ] {
+            reporter
+                .headers
+                .insert(name.to_string(), Secret::Value(value.to_string()));
+        }
+        reporter.auth = Some(AuthConfig::Basic {
+            username: "user:name".to_string(),
+            password: Secret::Env("VM_PASSWORD".to_string()),
+        });
+        let config = config_with(reporter);
+
+        let result = run_validation_chain(&config).err().unwrap();
+        let paths: Vec<&str> = result.iter().map(|err| err.path.as_str()).collect();
+
+        assert_eq!(
+            paths,
+            vec![
+                "reporter.headers.Content-Encoding",
+                "reporter.headers.X-Bad-Value",
+                "reporter.headers.bad header",
+                "reporter.auth.basic.username",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tls() {
+        let tls = TlsConfig {
+            ca_file: Some(PathBuf::from("/nonexistent/ca.pem")),
+            cert_file: None,
+            key_file: None,
+            server_name: None,
+        };
+
+        match push_url_is_valid("https://vm.example.com/api", Some(&tls)).unwrap_err() {
+            LogicError::Tls(_) => (),
+            err => panic!("wrong error: {:?}", err),
+        }
+        match push_url_is_valid("http://vm.example.com/api", Some(&tls)).unwrap_err() {
+            LogicError::TlsRequiresHttps => (),
+            err => panic!("wrong error: {:?}", err),
+        }
+
+        let mut reporter = reporter("https://vm.example.com/api");
+        reporter.tls = Some(TlsConfig {
+            ca_file: None,
+            cert_file: None,
+            key_file: Some(PathBuf::from("/etc/palantir/client.key")),
+            server_name: None,
+        });
+        let config = config_with(reporter);
+
+        let result = run_validation_chain(&config).err().unwrap();
+
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].path, "reporter.tls");
+    }
+
+    #[test]
+    fn test_targets() {
+        let spool = SpoolConfig {
+            path: PathBuf::from("/var/lib/palantir/spool"),
+            max_size_bytes: 1024 * 1024,
+        };
+        let mut primary = reporter("http://localhost:8428/api/v1/import/prometheus");
+        primary.spool = Some(spool.clone());
+        let mut longterm = reporter("http://longterm:8428/api/v1/import/prometheus");
+        longterm.spool = Some(spool);
+        longterm
+            .extra_labels
+            .insert("with-dash".to_string(), "x".to_string());
+        let mut config = config_with(primary);
+        config.targets = vec![
+            ("longterm".to_string(), longterm),
+            (
+                "default".to_string(),
+                reporter("http://localhost:8428/api/v1/import/prometheus"),
+            ),
+        ]
+        .into_iter()
+        .collect();
+
+        let result = run_validation_chain(&config).err().unwrap();
+        let paths: Vec<&str> = result.iter().map(|err| err.path.as_str()).collect();
+
+        assert_eq!(
+            paths,
+            vec![
+                "targets.longterm.extra_labels.with-dash",
+                "targets.longterm.spool.path",
+                "targets.default",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scrape_address_used_by_listener() {
+        let config = Config {
+            listeners: vec![ListenerType::HTTP(HTTPConfig {
+                address: IpAddr::from(Ipv4Addr::UNSPECIFIED),
+                port: 9100,
+                max_body_size: 1024 * 1024,
+            })],
+            scrape: Some(ScrapeConfig {
+                address: IpAddr::from(Ipv4Addr::LOCALHOST),
+                port: 9100,
+            }),
+            ..config_with(reporter("http://localhost:8428/api/v1/import/prometheus"))
+        };
+
+        let result = run_validation_chain(&config).err().unwrap();
+
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].path, "scrape.port");
+    }
+
+    #[test]
+    fn test_destinations() {
+        let mut remote_write = reporter("http://localhost:8428/api/v1/import/prometheus");
+        remote_write.vm_import_url = None;
+        remote_write.remote_write_url = Some("http://mimir:9009/api/v1/push".to_string());
+        let mut gzip = remote_write.clone();
+        gzip.compression = Compression::Gzip;
+        let mut both = reporter("http://localhost:8428/api/v1/import/prometheus");
+        both.remote_write_url = remote_write.remote_write_url.clone();
+        let mut none = remote_write.clone();
+        none.remote_write_url = None;
+        let mut influx = none.clone();
+        influx.influx_write_url = Some("http://influx:8086/write?db=apm".to_string());
+        influx.compression = Compression::Gzip;
+        let mut zstd = influx.clone();
+        zstd.compression = Compression::Zstd;
+        let mut config = config_with(remote_write);
+        config.targets = vec![
+            ("both".to_string(), both),
+            ("gzip".to_string(), gzip),
+            ("influx".to_string(), influx),
+            ("none".to_string(), none),
+            ("zstd".to_string(), zstd),
+        ]
+        .into_iter()
+        .collect();
+
+        let result = run_validation_chain(&config).err().unwrap();
+        let paths: Vec<&str> = result.iter().map(|err| err.path.as_str()).collect();
+
+        assert_eq!(
+            paths,
+            vec![
+                "targets.both",
+                "targets.gzip.compression",
+                "targets.none",
+                "targets.zstd.compression",
+            ]
+        );
+        match &result[0].error {
+            LogicError::SeveralDestinations(fields) => {
+                assert_eq!(fields, &vec!["vm_import_url", "remote_write_url"])
+            }
+            err => panic!("wrong error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_graphite_destination() {
+        let graphite = |address: &str| -> ReporterConfig {
+            serde_yaml::from_str(&format!("graphite_address: \"{}\"", address)).unwrap()
+        };
+        let mut template = graphite("localhost:2003");
+        template.graphite_path_template = "apm.{realm".to_string();
+        let mut gzip = graphite("localhost:2003");
+        gzip.compression = Compression::Gzip;
+        let mut tls = graphite("localhost:2003");
+        tls.tls = Some(serde_yaml::from_str("server_name: graphite").unwrap());
+        let mut config = config_with(graphite("localhost:2003"));
+        config.targets = vec![
+            ("gzip".to_string(), gzip),
+            ("path".to_string(), graphite("localhost:2003/metrics")),
+            ("port".to_string(), graphite("localhost")),
+            ("template".to_string(), template),
+            ("tls".to_string(), tls),
+        ]
+        .into_iter()
+        .collect();
+
+        let result = run_validation_chain(&config).err().unwrap();
+        let paths: Vec<&str> = result.iter().map(|err| err.path.as_str()).collect();
+
+        assert_eq!(
+            paths,
+            vec![
+                "targets.gzip.compression",
+                "targets.path.graphite_address",
+                "targets.port.graphite_address",
+                "targets.template.graphite_path_template",
+                "targets.tls.graphite_address",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_file_destination() {
+        let file = |dir: &str| -> ReporterConfig {
+            serde_yaml::from_str(&format!("file:\n  dir: {}", dir)).unwrap()
+        };
+        let mut small = file("/var/lib/palantir/small");
+        small.file.as_mut().unwrap().max_file_bytes = 1024;
+        small.file.as_mut().unwrap().max_files = 0;
+        let mut zstd = file("/var/lib/palantir/zstd");
+        zstd.compression = Compression::Zstd;
+        let mut config = config_with(file("/var/lib/palantir/reports"));
+        config.targets = vec![
+            ("same".to_string(), file("/var/lib/palantir/reports")),
+            ("small".to_string(), small),
+            ("zstd".to_string(), zstd),
+        ]
+        .into_iter()
+        .collect();
+
+        let result = run_validation_chain(&config).err().unwrap();
+        let paths: Vec<&str> = result.iter().map(|err| err.path.as_str()).collect();
+
+        assert_eq!(
+            paths,
+            vec![
+                "targets.small.file.max_file_bytes",
+                "targets.small.file.max_files",
+                "targets.same.file.dir",
+            ]
+        );
+    }
 }
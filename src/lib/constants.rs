@@ -11,12 +11,32 @@ pub const ACTION_SPAN_TAG_NAME: &str = "palantir_span";
 pub const ACTION_METRIC_NAME: &str = "palantir_apm";
 pub const UNTRACKED_ACTION_KIND_NAME: &str = "palantir_untracked";
 pub const TOTAL_ACTION_KIND_NAME: &str = "palantir_total";
+/// time agent takes to process a message
+pub const HANDLE_TIME_METRIC_NAME: &str = "request_handle_time";
+
+/// env variable with config file path, used when path is not passed as an argument
+pub const CONFIG_PATH_ENV: &str = "PALANTIR_CONFIG";
+
+/// `PALANTIR__REPORTER__VM_IMPORT_URL` overrides `reporter.vm_import_url`
+pub const CONFIG_ENV_PREFIX: &str = "PALANTIR__";
+pub const CONFIG_ENV_SEPARATOR: &str = "__";
+
+/// max number of messages waiting for the registry
+pub const PIPELINE_CAPACITY: usize = 65536;
 
 pub const EXTRA_LABEL_PREFIX: &str = "PALANTIR_LABEL_";
 lazy_static! {
     pub static ref EXTRA_LABEL_REGEX: Regex = Regex::new("^[0-9a-zA-Z\\-_]+$").unwrap();
+    pub static ref LABEL_NAME_REGEX: Regex = Regex::new("^[a-zA-Z_][a-zA-Z0-9_]*$").unwrap();
 }
 
+/// labels set by the agent itself, extra labels can't use them
+pub const RESERVED_LABEL_PREFIX: &str = "palantir_";
+pub const RESERVED_LABEL_NAMES: [&str; 4] = ["__name__", "generation", "vmrange", "le"];
+
+/// name of target configured by `reporter` section
+pub const DEFAULT_TARGET_NAME: &str = "default";
+
 #[cfg(test)]
 mod tests {
     use super::EXTRA_LABEL_REGEX;
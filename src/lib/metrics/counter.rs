@@ -0,0 +1,45 @@
+use crate::metrics::sample::Sample;
+use crate::metrics::tag::Tag;
+use crate::metrics::traits::PrometheusMetric;
+
+/// monotonic counter
+pub struct Counter {
+    name: String,
+    tags: Vec<Tag>,
+    value: u64,
+}
+
+impl Counter {
+    pub fn new(name: String, tags: Vec<Tag>) -> Self {
+        Self {
+            name,
+            tags,
+            value: 0,
+        }
+    }
+
+    pub fn inc(&mut self) {
+        self.add(1);
+    }
+
+    pub fn add(&mut self, value: u64) {
+        self.value = self.value.wrapping_add(value);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value
+    }
+}
+
+impl PrometheusMetric for Counter {
+    fn serialize_prometheus(&self) -> Vec<String> {
+        self.samples()
+            .iter()
+            .map(|sample| sample.to_exposition())
+            .collect()
+    }
+
+    fn samples(&self) -> Vec<Sample> {
+        vec![Sample::new(&self.name, self.tags.clone(), self.value)]
+    }
+}
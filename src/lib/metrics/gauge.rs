@@ -0,0 +1,41 @@
+use crate::metrics::sample::Sample;
+use crate::metrics::tag::Tag;
+use crate::metrics::traits::PrometheusMetric;
+
+/// last set value
+pub struct Gauge {
+    name: String,
+    tags: Vec<Tag>,
+    value: u64,
+}
+
+impl Gauge {
+    pub fn new(name: String, tags: Vec<Tag>) -> Self {
+        Self {
+            name,
+            tags,
+            value: 0,
+        }
+    }
+
+    pub fn set(&mut self, value: u64) {
+        self.value = value;
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value
+    }
+}
+
+impl PrometheusMetric for Gauge {
+    fn serialize_prometheus(&self) -> Vec<String> {
+        self.samples()
+            .iter()
+            .map(|sample| sample.to_exposition())
+            .collect()
+    }
+
+    fn samples(&self) -> Vec<Sample> {
+        vec![Sample::new(&self.name, self.tags.clone(), self.value)]
+    }
+}
@@ -1,5 +1,7 @@
+use crate::metrics::sample::{escape_label_value, Sample};
 use crate::metrics::tag::Tag;
 use crate::metrics::traits::PrometheusMetric;
+use std::time::SystemTime;
 
 const E2_MIN: usize = 8;
 const E2_MAX: usize = 36;
@@ -56,6 +58,15 @@ fn get_vmrange(bucket_no: usize) -> String {
     };
 }
 
+/// upper bound of cumulative bucket, as in `le` label
+fn get_le(bucket_no: usize) -> String {
+    if bucket_no == BUCKETS_COUNT - 1 {
+        "+Inf".to_string()
+    } else {
+        BUCKET_UPPER_BOUNDS[bucket_no].to_string()
+    }
+}
+
 fn get_bucket_no(value: u64) -> usize {
     for (no, upper) in BUCKET_UPPER_BOUNDS.iter().enumerate() {
         if value <= *upper {
@@ -66,24 +77,32 @@ fn get_bucket_no(value: u64) -> usize {
     return BUCKETS_COUNT - 1;
 }
 
+#[derive(Clone)]
 pub struct Histogram {
+    /// sum of values tracked by each bucket
     buckets: [u64; BUCKETS_COUNT],
+    /// number of values tracked by each bucket
+    counts: [u64; BUCKETS_COUNT],
     count: u64,
     sum: u64,
     tags: Vec<Tag>,
     generation: u64,
     name: String,
+    /// when values started to accumulate, moves on reset
+    started: SystemTime,
 }
 
 impl Histogram {
     pub fn new(name: String, tags: Vec<Tag>) -> Self {
         return Self {
             buckets: [0u64; BUCKETS_COUNT],
+            counts: [0u64; BUCKETS_COUNT],
             count: 0,
             sum: 0,
             tags,
             name,
             generation: 1,
+            started: SystemTime::now(),
         };
     }
 
@@ -92,7 +111,37 @@ impl Histogram {
         self.sum = 0;
         self.count = 0;
         self.buckets = [0u64; BUCKETS_COUNT];
+        self.counts = [0u64; BUCKETS_COUNT];
         self.generation += 1;
+        self.started = SystemTime::now();
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn tags(&self) -> &[Tag] {
+        &self.tags
+    }
+
+    pub fn started(&self) -> SystemTime {
+        self.started
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    /// (upper bound, number of tracked values) per bucket, last bound is u64::MAX
+    pub fn bucket_counts(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        BUCKET_UPPER_BOUNDS
+            .iter()
+            .cloned()
+            .zip(self.counts.iter().cloned())
     }
 
     /// Track value  
@@ -109,6 +158,7 @@ impl Histogram {
             if let Some(result_bucket) = result_bucket {
                 should_reset = false;
                 self.buckets[bucket_no] = result_bucket;
+                self.counts[bucket_no] += 1;
                 self.sum = result_sum;
                 self.count += 1;
             }
@@ -128,7 +178,11 @@ impl PrometheusMetric for Histogram {
         common_tags.push_str(&*format!("{{generation=\"{}\"", self.generation));
         for tag in &self.tags {
             common_tags.push(',');
-            common_tags.push_str(&*format!("{}=\"{}\"", tag.key, tag.value))
+            common_tags.push_str(&*format!(
+                "{}=\"{}\"",
+                tag.key,
+                escape_label_value(&tag.value)
+            ))
         }
         common_tags.shrink_to_fit();
 
@@ -165,6 +219,35 @@ impl PrometheusMetric for Histogram {
 
         result
     }
+
+    /// cumulative `le` buckets, every bucket is listed so series don't come and go
+    /// generation is left out, Prometheus handles counter resets on its own
+    fn samples(&self) -> Vec<Sample> {
+        let mut result: Vec<Sample> = Vec::with_capacity(self.counts.len() + 2);
+        let bucket_name = format!("{}_bucket", &self.name);
+        let mut cumulative = 0u64;
+        for (bucket_no, count) in self.counts.iter().enumerate() {
+            cumulative += count;
+            let mut tags = self.tags.clone();
+            tags.push(Tag {
+                key: "le".to_string(),
+                value: get_le(bucket_no),
+            });
+            result.push(Sample::new(&bucket_name, tags, cumulative));
+        }
+        result.push(Sample::new(
+            &format!("{}_count", &self.name),
+            self.tags.clone(),
+            self.count,
+        ));
+        result.push(Sample::new(
+            &format!("{}_sum", &self.name),
+            self.tags.clone(),
+            self.sum,
+        ));
+
+        result
+    }
 }
 
 #[cfg(test)]
@@ -323,4 +406,30 @@ mod tests {
             String::from("hist_sum{generation=\"1\",key=\"value\"} 1\n")
         );
     }
+
+    #[test]
+    fn test_serialize_exposition() {
+        let mut histogram = Histogram::new(
+            String::from("hist"),
+            vec![Tag {
+                key: "app".to_string(),
+                value: "shop".to_string(),
+            }],
+        );
+        histogram.track(1);
+        histogram.track(2);
+        histogram.track(300);
+
+        let rows = histogram.serialize_exposition();
+
+        assert_eq!(rows.len(), BUCKETS_COUNT + 2);
+        assert_eq!(rows[0], "hist_bucket{app=\"shop\",le=\"255\"} 2\n");
+        assert_eq!(rows[1], "hist_bucket{app=\"shop\",le=\"511\"} 3\n");
+        assert_eq!(
+            rows[BUCKETS_COUNT - 1],
+            "hist_bucket{app=\"shop\",le=\"+Inf\"} 3\n"
+        );
+        assert_eq!(rows[BUCKETS_COUNT], "hist_count{app=\"shop\"} 3\n");
+        assert_eq!(rows[BUCKETS_COUNT + 1], "hist_sum{app=\"shop\"} 303\n");
+    }
 }
@@ -1,4 +1,7 @@
+pub mod counter;
+pub mod gauge;
 pub mod histogram;
+pub mod sample;
 pub mod tag;
 
 pub mod traits;
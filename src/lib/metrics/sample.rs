@@ -0,0 +1,80 @@
+use crate::metrics::tag::Tag;
+
+/// single point of a series in Prometheus data model
+/// exporters that don't push Prometheus text build their payloads from these
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub name: String,
+    pub tags: Vec<Tag>,
+    pub value: u64,
+}
+
+impl Sample {
+    pub fn new(name: &str, tags: Vec<Tag>, value: u64) -> Self {
+        Self {
+            name: name.to_string(),
+            tags,
+            value,
+        }
+    }
+
+    /// row of text exposition format, without timestamp
+    pub fn to_exposition(&self) -> String {
+        format!("{} {}\n", self.series(), self.value)
+    }
+
+    /// row of text exposition format, as VictoriaMetrics imports it
+    pub fn to_exposition_at(&self, timestamp_ms: u128) -> String {
+        format!("{} {} {}\n", self.series(), self.value, timestamp_ms)
+    }
+
+    /// name with escaped labels
+    fn series(&self) -> String {
+        if self.tags.is_empty() {
+            return self.name.clone();
+        }
+        let tags: Vec<String> = self
+            .tags
+            .iter()
+            .map(|tag| format!("{}=\"{}\"", tag.key, escape_label_value(&tag.value)))
+            .collect();
+        format!("{}{{{}}}", self.name, tags.join(","))
+    }
+}
+
+/// label value as text exposition format quotes it, clients may send anything in tags
+pub fn escape_label_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::sample::Sample;
+    use crate::metrics::tag::Tag;
+
+    #[test]
+    fn test_to_exposition_escapes_label_values() {
+        let sample = Sample::new(
+            "requests_total",
+            vec![
+                Tag::new("path", "C:\\temp\\"),
+                Tag::new("name", "say \"hi\"\n} 1\nfake_total 2"),
+            ],
+            3,
+        );
+
+        assert_eq!(
+            sample.to_exposition(),
+            "requests_total{path=\"C:\\\\temp\\\\\",name=\"say \\\"hi\\\"\\n} 1\\nfake_total 2\"} 3\n"
+        );
+    }
+}
@@ -1,11 +1,20 @@
 use palantir_proto::palantir::shared::tag::Tag as ProtoTag;
 
-#[derive(Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Tag {
     pub key: String,
     pub value: String,
 }
 
+impl Tag {
+    pub fn new(key: &str, value: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            value: value.to_string(),
+        }
+    }
+}
+
 impl From<&ProtoTag> for Tag {
     fn from(t: &ProtoTag) -> Self {
         Self {
@@ -1,3 +1,16 @@
+use crate::metrics::sample::Sample;
+
 pub trait PrometheusMetric {
     fn serialize_prometheus(&self) -> Vec<String>;
+
+    /// series as Prometheus sees them, histograms are split into cumulative `le` buckets
+    fn samples(&self) -> Vec<Sample>;
+
+    /// text exposition format for Prometheus scrapes
+    fn serialize_exposition(&self) -> Vec<String> {
+        self.samples()
+            .iter()
+            .map(|sample| sample.to_exposition())
+            .collect()
+    }
 }
@@ -1 +1,3 @@
 pub mod checksum;
+pub mod template;
+pub mod tls;
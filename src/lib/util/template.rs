@@ -0,0 +1,110 @@
+use crate::constants::LABEL_NAME_REGEX;
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum TemplateError {
+    Empty,
+    /// `{` without matching `}` or `}` without `{`
+    Unbalanced,
+    InvalidPlaceholder(String),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "template is empty"),
+            Self::Unbalanced => write!(f, "unbalanced braces"),
+            Self::InvalidPlaceholder(name) => write!(f, "{{{}}} is not a valid placeholder", name),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    Placeholder(String),
+}
+
+/// text with `{name}` placeholders, e.g. `{realm}.{application}.{span}`
+#[derive(Debug, PartialEq)]
+pub struct PathTemplate {
+    segments: Vec<Segment>,
+}
+
+impl PathTemplate {
+    pub fn parse(template: &str) -> Result<Self, TemplateError> {
+        if template.is_empty() {
+            return Err(TemplateError::Empty);
+        }
+        let mut segments = Vec::new();
+        let mut rest = template;
+        while !rest.is_empty() {
+            match rest.find(['{', '}']) {
+                None => {
+                    segments.push(Segment::Literal(rest.to_string()));
+                    break;
+                }
+                Some(start) if rest[start..].starts_with('}') => {
+                    return Err(TemplateError::Unbalanced)
+                }
+                Some(start) => {
+                    if start > 0 {
+                        segments.push(Segment::Literal(rest[..start].to_string()));
+                    }
+                    let end = start + rest[start..].find('}').ok_or(TemplateError::Unbalanced)?;
+                    let name = &rest[start + 1..end];
+                    if !LABEL_NAME_REGEX.is_match(name) {
+                        return Err(TemplateError::InvalidPlaceholder(name.to_string()));
+                    }
+                    segments.push(Segment::Placeholder(name.to_string()));
+                    rest = &rest[end + 1..];
+                }
+            }
+        }
+        Ok(Self { segments })
+    }
+
+    /// placeholders are replaced with what `value` returns for their names
+    pub fn render<F: Fn(&str) -> String>(&self, value: F) -> String {
+        let mut rendered = String::with_capacity(64);
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => rendered.push_str(text),
+                Segment::Placeholder(name) => rendered.push_str(&value(name)),
+            }
+        }
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::template::{PathTemplate, TemplateError};
+
+    #[test]
+    fn test_render() {
+        let template = PathTemplate::parse("apm.{realm}.{span}_us").unwrap();
+
+        let path = template.render(|name| name.to_uppercase());
+
+        assert_eq!(path, "apm.REALM.SPAN_us");
+    }
+
+    #[test]
+    fn test_invalid() {
+        let cases = vec![
+            ("", TemplateError::Empty),
+            ("{realm", TemplateError::Unbalanced),
+            ("realm}", TemplateError::Unbalanced),
+            (
+                "{realm}.{{span}}",
+                TemplateError::InvalidPlaceholder("{span".to_string()),
+            ),
+            ("{}", TemplateError::InvalidPlaceholder("".to_string())),
+        ];
+
+        for (template, expected) in cases {
+            assert_eq!(PathTemplate::parse(template).unwrap_err(), expected);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use crate::config::defs::TlsConfig;
+use rustls_pemfile::Item;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Error};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio_rustls::rustls::{
+    Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore, ServerName,
+};
+
+#[derive(Debug)]
+pub enum TlsError {
+    Read(PathBuf, Error),
+    NoCertificates(PathBuf),
+    InvalidCertificate(PathBuf),
+    NoPrivateKey(PathBuf),
+    /// client certificate and key have to be set together
+    IncompleteIdentity,
+    InvalidServerName(String),
+    Rustls(tokio_rustls::rustls::Error),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(path, err) => write!(f, "unable to read {:?}, {}", path, err),
+            Self::NoCertificates(path) => write!(f, "no certificates found in {:?}", path),
+            Self::InvalidCertificate(path) => write!(f, "invalid CA certificate in {:?}", path),
+            Self::NoPrivateKey(path) => write!(f, "no private key found in {:?}", path),
+            Self::IncompleteIdentity => {
+                write!(f, "cert_file and key_file must be set together")
+            }
+            Self::InvalidServerName(name) => write!(f, "{} is not a valid server name", name),
+            Self::Rustls(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+fn open(path: &Path) -> Result<BufReader<File>, TlsError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|err| TlsError::Read(path.to_path_buf(), err))
+}
+
+/// all certificates of PEM bundle
+pub fn load_certificates(path: &Path) -> Result<Vec<Certificate>, TlsError> {
+    let certs = rustls_pemfile::certs(&mut open(path)?)
+        .map_err(|err| TlsError::Read(path.to_path_buf(), err))?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificates(path.to_path_buf()));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+/// first PKCS#8, PKCS#1 (RSA) or SEC1 (EC) key of PEM file
+pub fn load_private_key(path: &Path) -> Result<PrivateKey, TlsError> {
+    let items = rustls_pemfile::read_all(&mut open(path)?)
+        .map_err(|err| TlsError::Read(path.to_path_buf(), err))?;
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| TlsError::NoPrivateKey(path.to_path_buf()))
+}
+
+/// name sent in SNI and checked against server certificate, brackets of IPv6 hosts are dropped
+pub fn server_name(name: &str) -> Result<ServerName, TlsError> {
+    let host = name.trim_start_matches('[').trim_end_matches(']');
+    ServerName::try_from(host).map_err(|_| TlsError::InvalidServerName(name.to_string()))
+}
+
+/// modification times of configured files (None if unreadable), they change once files are rotated
+pub fn files_modified(tls: Option<&TlsConfig>) -> Vec<Option<SystemTime>> {
+    let files = match tls {
+        Some(tls) => vec![&tls.ca_file, &tls.cert_file, &tls.key_file],
+        None => return Vec::new(),
+    };
+    files
+        .into_iter()
+        .flatten()
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// trusts only `ca_file` if set, bundled Mozilla roots otherwise
+fn root_store(tls: Option<&TlsConfig>) -> Result<RootCertStore, TlsError> {
+    let mut roots = RootCertStore::empty();
+    match tls.and_then(|tls| tls.ca_file.as_ref()) {
+        Some(path) => {
+            for cert in load_certificates(path)? {
+                roots
+                    .add(&cert)
+                    .map_err(|_| TlsError::InvalidCertificate(path.clone()))?;
+            }
+        }
+        None => roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                ta.subject,
+                ta.spki,
+                ta.name_constraints,
+            )
+        })),
+    }
+    Ok(roots)
+}
+
+/// reads every configured file, callers keep the result until `files_modified` changes
+pub fn client_config(tls: Option<&TlsConfig>) -> Result<ClientConfig, TlsError> {
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(root_store(tls)?);
+    let identity = tls.map(|tls| (tls.cert_file.as_ref(), tls.key_file.as_ref()));
+    let config = match identity {
+        Some((Some(cert_file), Some(key_file))) => builder
+            .with_single_cert(load_certificates(cert_file)?, load_private_key(key_file)?)
+            .map_err(TlsError::Rustls)?,
+        Some((Some(_), None)) | Some((None, Some(_))) => return Err(TlsError::IncompleteIdentity),
+        _ => builder.with_no_client_auth(),
+    };
+    if let Some(name) = tls.and_then(|tls| tls.server_name.as_ref()) {
+        server_name(name)?;
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::defs::TlsConfig;
+    use crate::util::tls::{
+        client_config, files_modified, load_certificates, server_name, TlsError,
+    };
+    use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn tls(yaml: &str) -> TlsConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_default_roots() {
+        client_config(None).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let dir = std::env::temp_dir().join(format!("palantir-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let not_pem = dir.join("not.pem");
+        std::fs::write(&not_pem, "not a certificate").unwrap();
+
+        match load_certificates(&not_pem).unwrap_err() {
+            TlsError::NoCertificates(path) => assert_eq!(path, not_pem),
+            err => panic!("wrong error: {:?}", err),
+        }
+        match client_config(Some(&tls("ca_file: /nonexistent/ca.pem"))).unwrap_err() {
+            TlsError::Read(path, _) => assert_eq!(path, PathBuf::from("/nonexistent/ca.pem")),
+            err => panic!("wrong error: {:?}", err),
+        }
+        match client_config(Some(&tls("cert_file: /etc/palantir/client.pem"))).unwrap_err() {
+            TlsError::IncompleteIdentity => (),
+            err => panic!("wrong error: {:?}", err),
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_files_modified() {
+        let dir = std::env::temp_dir().join(format!("palantir-rotate-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ca = dir.join("ca.pem");
+        let file = std::fs::File::create(&ca).unwrap();
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(1))
+            .unwrap();
+        let config = tls(&format!(
+            "ca_file: {}\nkey_file: /nonexistent/key.pem",
+            ca.display()
+        ));
+
+        let before = files_modified(Some(&config));
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(2))
+            .unwrap();
+        let after = files_modified(Some(&config));
+
+        assert_eq!(before.len(), 2);
+        assert_eq!(before[1], None);
+        assert_ne!(before, after);
+        assert!(files_modified(None).is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_server_name() {
+        server_name("vm.example.com").unwrap();
+        server_name("[::1]").unwrap();
+        server_name("10.0.0.1").unwrap();
+        assert!(server_name("not a host").is_err());
+    }
+}
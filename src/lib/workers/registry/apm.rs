@@ -1,22 +1,137 @@
-use crate::config::defs::ReporterConfig;
+use crate::config::defs::{ExporterKind, ReporterConfig};
+use crate::constants as c;
 use crate::metrics::histogram::metric::Histogram;
+use crate::workers::registry::error::RegistryError;
+use crate::workers::registry::exporter::{Exporter, ExporterFactory};
+use crate::workers::registry::file::FileExporter;
+use crate::workers::registry::graphite::GraphiteExporter;
 use crate::workers::registry::hc::HistogramCollection;
+use crate::workers::registry::influx::InfluxExporter;
+use crate::workers::registry::otlp::OtlpExporter;
 use crate::workers::registry::processor::Processor;
+use crate::workers::registry::remote_write::RemoteWriteExporter;
 use crate::workers::registry::reporter::Reporter;
+use crate::workers::registry::scrape::ScrapeServer;
+use crate::workers::registry::vm::VmExporter;
+use log::{error, info};
 use palantir_proto::palantir::request::request::Message as ProtoMessage;
-use std::collections::HashMap;
-use std::sync::mpsc::{channel, Receiver};
+use std::collections::{BTreeMap, HashMap};
+use std::net::TcpListener;
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
+use tokio::task::{JoinHandle, LocalSet};
 
+/// how often reporters are started and stopped to match configured targets
+const TARGETS_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+type ReporterTask = (
+    Option<ExporterKind>,
+    Arc<Mutex<ReporterConfig>>,
+    JoinHandle<Result<(), RegistryError>>,
+);
+
+/// exporter speaking protocol of target's destination
+pub fn builtin_exporter(target: &str, config: &ReporterConfig) -> Box<dyn Exporter> {
+    let target = target.to_string();
+    match config.destination().map(|(kind, _)| kind) {
+        Some(ExporterKind::RemoteWrite) => Box::new(RemoteWriteExporter::new(target)),
+        Some(ExporterKind::Influx) => Box::new(InfluxExporter::new(target)),
+        Some(ExporterKind::Graphite) => Box::new(GraphiteExporter::new(target)),
+        Some(ExporterKind::Otlp) => Box::new(OtlpExporter::new(target)),
+        Some(ExporterKind::File) => Box::new(FileExporter::new(target)),
+        // validated config always has a destination
+        Some(ExporterKind::VmImport) | None => Box::new(VmExporter::new(target)),
+    }
+}
+
+/// runs a reporter per target, so a slow or failing target doesn't delay the others
+/// reporters of removed targets are stopped, crashed ones are restarted
+async fn run_reporters(
+    client_metrics: Arc<Mutex<HashMap<u64, Arc<HistogramCollection>>>>,
+    handle_time: Arc<Mutex<Histogram>>,
+    keepalive_tx: Sender<()>,
+    targets: Arc<Mutex<BTreeMap<String, ReporterConfig>>>,
+    exporter: ExporterFactory,
+) -> Result<(), RegistryError> {
+    let mut running: HashMap<String, ReporterTask> = HashMap::new();
+    loop {
+        let finished: Vec<String> = running
+            .iter()
+            .filter(|(_, (_, _, task))| task.is_finished())
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in finished {
+            let (_, _, task) = running.remove(&name).unwrap();
+            match task.await {
+                // reporter stops only when processor is gone
+                Ok(Err(err)) => return Err(err),
+                Ok(Ok(())) => error!("Reporter of target {} stopped, restarting it", name),
+                Err(err) => error!(
+                    "Reporter of target {} crashed, restarting it, {}",
+                    name, err
+                ),
+            }
+        }
+
+        // config may be reloaded, reporters re-read their section on every report
+        let configured = targets.lock().unwrap().clone();
+        running.retain(|name, (_, _, task)| {
+            let keep = configured.contains_key(name);
+            if !keep {
+                info!("Stopping reporter of target {}", name);
+                task.abort();
+            }
+            keep
+        });
+        for (name, config) in configured {
+            let kind = config.destination().map(|(kind, _)| kind);
+            if let Some((running_kind, current, task)) = running.get(&name) {
+                if *running_kind == kind {
+                    *current.lock().unwrap() = config;
+                    continue;
+                }
+                info!(
+                    "Protocol of target {} changed, restarting its reporter",
+                    name
+                );
+                task.abort();
+            }
+
+            info!("Starting reporter of target {}", name);
+            let exporter = exporter(&name, &config);
+            let config = Arc::new(Mutex::new(config));
+            let mut reporter = Reporter::new(
+                name.clone(),
+                client_metrics.clone(),
+                handle_time.clone(),
+                keepalive_tx.clone(),
+                config.clone(),
+                exporter,
+            );
+            let task = tokio::task::spawn_local(async move { reporter.run().await });
+            running.insert(name, (kind, config, task));
+        }
+
+        tokio::time::sleep(TARGETS_SYNC_INTERVAL).await;
+    }
+}
+
+/// `targets` may be updated while registry is running, see `run_reporters`
+/// `scrape` is bound socket of scrape endpoint, if it's enabled
+/// `exporter` is called whenever reporter of a target is (re)started, `builtin_exporter` may be used
+/// for targets it doesn't handle itself
 pub fn run_registry(
     rx: Receiver<ProtoMessage>,
-    reporter: &'static ReporterConfig,
+    targets: Arc<Mutex<BTreeMap<String, ReporterConfig>>>,
+    scrape: Option<TcpListener>,
+    exporter: ExporterFactory,
 ) -> thread::Result<()> {
-    let client_metrics: Arc<Mutex<HashMap<u64, HistogramCollection>>> =
+    let client_metrics: Arc<Mutex<HashMap<u64, Arc<HistogramCollection>>>> =
         Arc::new(Mutex::new(HashMap::new()));
     let handle_time: Arc<Mutex<Histogram>> = Arc::new(Mutex::new(Histogram::new(
-        "request_handle_time".to_string(),
+        c::HANDLE_TIME_METRIC_NAME.to_string(),
         Vec::new(),
     )));
 
@@ -40,18 +155,23 @@ pub fn run_registry(
             .build()
             .expect("Unable to create runtime");
 
-        let mut reporter = Reporter::new(
-            metrics_clone,
-            handle_clone,
-            reporter_tx,
-            &reporter.vm_import_url,
-        );
+        // reporters hold encoders that can't be sent between threads
+        let local = LocalSet::new();
         #[allow(unused_must_use)]
         {
-            runtime.block_on(reporter.run());
+            local.block_on(
+                &runtime,
+                run_reporters(metrics_clone, handle_clone, reporter_tx, targets, exporter),
+            );
         }
     });
 
+    // scrape endpoint thread isn't joined, it only reads collections
+    if let Some(listener) = scrape {
+        let server = ScrapeServer::new(listener, client_metrics, handle_time);
+        thread::spawn(move || server.run());
+    }
+
     processor_handle.join()?;
     reporter_handle.join()?;
 
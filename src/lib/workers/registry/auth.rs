@@ -0,0 +1,191 @@
+use crate::config::defs::{AuthConfig, ReporterConfig, Secret};
+use hyper::header::{HeaderName, HeaderValue, AUTHORIZATION};
+use hyper::HeaderMap;
+use log::info;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// never contains secret values, safe to log
+#[derive(Debug)]
+pub enum AuthError {
+    Secret(Error),
+    InvalidHeader(String),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Secret(err) => write!(f, "unable to read secret, {}", err),
+            Self::InvalidHeader(name) => write!(f, "invalid value of {} header", name),
+        }
+    }
+}
+
+impl From<Error> for AuthError {
+    fn from(err: Error) -> Self {
+        Self::Secret(err)
+    }
+}
+
+/// resolves secrets, files are re-read only when their modification time changes
+#[derive(Default)]
+pub struct SecretStore {
+    files: HashMap<PathBuf, (SystemTime, String)>,
+}
+
+impl SecretStore {
+    pub fn resolve(&mut self, secret: &Secret) -> Result<String, Error> {
+        match secret {
+            Secret::Value(value) => Ok(value.clone()),
+            Secret::Env(name) => std::env::var(name).map_err(|_| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!("env variable {} is not set", name),
+                )
+            }),
+            Secret::File(path) => self.read_file(path),
+        }
+    }
+
+    fn read_file(&mut self, path: &Path) -> Result<String, Error> {
+        let with_path = |err: Error| Error::new(err.kind(), format!("{:?}: {}", path, err));
+        let modified = fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(with_path)?;
+        if let Some((cached_modified, value)) = self.files.get(path) {
+            if *cached_modified == modified {
+                return Ok(value.clone());
+            }
+        }
+
+        // trailing newline is almost always an artifact of how the file was written
+        let value = fs::read_to_string(path)
+            .map_err(with_path)?
+            .trim_end_matches(&['\r', '\n'][..])
+            .to_string();
+        info!("Loaded secret from {:?}", path);
+        self.files
+            .insert(path.to_path_buf(), (modified, value.clone()));
+        Ok(value)
+    }
+}
+
+/// headers sent with every push, all of them are marked sensitive
+pub fn request_headers(
+    config: &ReporterConfig,
+    secrets: &mut SecretStore,
+) -> Result<HeaderMap, AuthError> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in config.headers.iter() {
+        let invalid = || AuthError::InvalidHeader(name.clone());
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid())?;
+        let mut value = HeaderValue::from_str(&secrets.resolve(value)?).map_err(|_| invalid())?;
+        value.set_sensitive(true);
+        headers.insert(name, value);
+    }
+
+    let authorization = match &config.auth {
+        None => return Ok(headers),
+        Some(AuthConfig::Basic { username, password }) => {
+            let credentials = format!("{}:{}", username, secrets.resolve(password)?);
+            format!("Basic {}", base64::encode(credentials))
+        }
+        Some(AuthConfig::Bearer(token)) => format!("Bearer {}", secrets.resolve(token)?),
+        Some(AuthConfig::Token(token)) => format!("Token {}", secrets.resolve(token)?),
+    };
+    let mut value = HeaderValue::from_str(&authorization)
+        .map_err(|_| AuthError::InvalidHeader(AUTHORIZATION.to_string()))?;
+    value.set_sensitive(true);
+    headers.insert(AUTHORIZATION, value);
+
+    Ok(headers)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::defs::{AuthConfig, ReporterConfig, Secret};
+    use crate::workers::registry::auth::{request_headers, SecretStore};
+    use hyper::header::AUTHORIZATION;
+
+    fn reporter(auth: AuthConfig) -> ReporterConfig {
+        let mut config: ReporterConfig =
+            serde_yaml::from_str("vm_import_url: http://vm:8428/api/v1/import/prometheus").unwrap();
+        config.auth = Some(auth);
+        config
+            .headers
+            .insert("AccountID".to_string(), Secret::Value("42".to_string()));
+        config
+    }
+
+    #[test]
+    fn test_basic_auth() {
+        let config = reporter(AuthConfig::Basic {
+            username: "agent".to_string(),
+            password: Secret::Value("s3cr3t".to_string()),
+        });
+
+        let headers = request_headers(&config, &mut SecretStore::default()).unwrap();
+
+        assert_eq!(headers["accountid"], "42");
+        assert_eq!(headers[AUTHORIZATION], "Basic YWdlbnQ6czNjcjN0");
+        assert!(headers[AUTHORIZATION].is_sensitive());
+        assert!(!format!("{:?}", headers).contains("YWdlbnQ6czNjcjN0"));
+    }
+
+    #[test]
+    fn test_bearer_from_file_reloaded() {
+        let path = std::env::temp_dir().join(format!("palantir-token-{}", std::process::id()));
+        std::fs::write(&path, "first\n").unwrap();
+        let config = reporter(AuthConfig::Bearer(Secret::File(path.clone())));
+        let mut secrets = SecretStore::default();
+
+        let first = request_headers(&config, &mut secrets).unwrap();
+        // make sure modification time differs on coarse-grained filesystems
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        std::fs::write(&path, "second").unwrap();
+        let second = request_headers(&config, &mut secrets).unwrap();
+
+        assert_eq!(first[AUTHORIZATION], "Bearer first");
+        assert_eq!(second[AUTHORIZATION], "Bearer second");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_influx_token() {
+        let config = reporter(AuthConfig::Token(Secret::Value("t0ken".to_string())));
+
+        let headers = request_headers(&config, &mut SecretStore::default()).unwrap();
+
+        assert_eq!(headers[AUTHORIZATION], "Token t0ken");
+        assert!(headers[AUTHORIZATION].is_sensitive());
+    }
+
+    #[test]
+    fn test_missing_secret() {
+        let config = reporter(AuthConfig::Bearer(Secret::Env(
+            "PALANTIR_TEST_MISSING_TOKEN".to_string(),
+        )));
+
+        let err = request_headers(&config, &mut SecretStore::default()).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "unable to read secret, env variable PALANTIR_TEST_MISSING_TOKEN is not set"
+        );
+    }
+
+    #[test]
+    fn test_secret_redacted() {
+        let secret = Secret::Value("s3cr3t".to_string());
+
+        assert_eq!(format!("{:?}", secret), "Value(\"<redacted>\")");
+        assert_eq!(
+            serde_yaml::to_string(&secret).unwrap().trim(),
+            "---\nvalue: \"<redacted>\""
+        );
+    }
+}
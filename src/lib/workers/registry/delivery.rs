@@ -0,0 +1,516 @@
+use crate::config::defs::{Compression, ExporterKind, ReporterConfig, SpoolConfig, TlsConfig};
+use crate::metrics::sample::Sample;
+use crate::metrics::traits::PrometheusMetric;
+use crate::util::tls::{client_config, files_modified};
+use crate::workers::registry::auth::{request_headers, SecretStore};
+use crate::workers::registry::connector::Connector;
+use crate::workers::registry::payload::Payload;
+use crate::workers::registry::push::{PushError, Pusher};
+use crate::workers::registry::self_metrics::SelfMetrics;
+use crate::workers::registry::spool::{Encoding, Spool};
+use crate::workers::registry::writer::SentPart;
+use hyper::body::Bytes;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, HeaderMap};
+use log::{error, info, warn};
+use std::fmt::Debug;
+use std::future::Future;
+use std::io::Result as IOResult;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::Semaphore;
+
+/// report as produced by exporter's encoding
+pub enum Report {
+    /// parts written by `ReportWriter`, pushes of them are already started
+    Streamed(Vec<SentPart>),
+    /// payloads encoded at once, pushed by `Delivery`
+    Encoded(Vec<Bytes>),
+}
+
+/// what http client depends on, client is rebuilt once any of it changes
+#[derive(Debug, Clone, PartialEq)]
+struct ClientSettings {
+    tls: Option<TlsConfig>,
+    /// see `files_modified`
+    tls_modified: Vec<Option<SystemTime>>,
+    connect_timeout: Duration,
+}
+
+/// what every http exporter needs to get payloads to a target:
+/// credentials, TLS, retries and spool for payloads that couldn't be pushed
+pub struct Delivery {
+    /// target name, used in logs
+    pub target: String,
+    /// spooled payloads are tagged with it, so they are only replayed with the same protocol
+    kind: ExporterKind,
+    spool: Option<(SpoolConfig, Spool)>,
+    pub self_metrics: SelfMetrics,
+    secrets: SecretStore,
+    /// reused between reports, so TLS setup isn't redone and connections are kept alive
+    client: Option<(ClientSettings, Client<Connector>)>,
+}
+
+impl Delivery {
+    pub fn new(target: String, kind: ExporterKind) -> Self {
+        Self {
+            self_metrics: SelfMetrics::new(&target),
+            target,
+            kind,
+            spool: None,
+            secrets: SecretStore::default(),
+            client: None,
+        }
+    }
+
+    /// spool is re-opened when its config changes
+    pub fn sync_spool(&mut self, config: &ReporterConfig) {
+        let current = self.spool.as_ref().map(|(spool_config, _)| spool_config);
+        if current == config.spool.as_ref() {
+            return;
+        }
+        self.spool =
+            config
+                .spool
+                .as_ref()
+                .and_then(|spool_config| match Spool::open(spool_config) {
+                    Ok(spool) => Some((spool_config.clone(), spool)),
+                    Err(err) => {
+                        error!("Unable to open spool {:?}, {:?}", spool_config.path, err);
+                        None
+                    }
+                });
+    }
+
+    /// `headers` are set by exporter on top of configured ones
+    /// None -> credentials or TLS files can't be loaded, pushes would fail anyway
+    pub fn pusher(
+        &mut self,
+        config: &ReporterConfig,
+        url: String,
+        headers: HeaderMap,
+    ) -> Option<Arc<Pusher>> {
+        let configured = match request_headers(config, &mut self.secrets) {
+            Ok(configured) => Some(configured),
+            Err(err) => {
+                error!("Unable to build request headers, {}", err);
+                None
+            }
+        };
+        let client = self.client(config);
+        let (mut configured, client) = match (configured, client) {
+            (Some(configured), Some(client)) => (configured, client),
+            _ => return None,
+        };
+        configured.extend(headers);
+
+        Some(Arc::new(Pusher {
+            target: self.target.clone(),
+            client,
+            url,
+            timeout: config.request_timeout(),
+            retry: config.retry.clone(),
+            max_retry_time: Duration::from_secs(config.period_seconds),
+            headers: configured,
+        }))
+    }
+
+    /// cached client, or a new one if settings or TLS files changed since it was built
+    /// None -> TLS files can't be loaded
+    fn client(&mut self, config: &ReporterConfig) -> Option<Client<Connector>> {
+        let settings = ClientSettings {
+            tls: config.tls.clone(),
+            tls_modified: files_modified(config.tls.as_ref()),
+            connect_timeout: config.connect_timeout(),
+        };
+        if let Some((current, client)) = self.client.as_ref() {
+            if *current == settings {
+                return Some(client.clone());
+            }
+        }
+
+        let tls = match client_config(settings.tls.as_ref()) {
+            Ok(tls) => tls,
+            Err(err) => {
+                error!("Unable to load TLS config, {}", err);
+                return None;
+            }
+        };
+        let mut http = HttpConnector::new();
+        http.set_connect_timeout(Some(settings.connect_timeout));
+        let server_name = settings
+            .tls
+            .as_ref()
+            .and_then(|tls| tls.server_name.clone());
+        let client = Client::builder().build(Connector::new(http, tls, server_name));
+        self.client = Some((settings, client.clone()));
+        Some(client)
+    }
+
+    /// pushes a report, spooled payloads go first so target receives points in order
+    /// `encode` gets pusher for parts streamed while encoding (None -> spool them) and agent's own metrics
+    pub async fn push_report<F, Fut, E>(
+        &mut self,
+        config: &ReporterConfig,
+        url: String,
+        headers: HeaderMap,
+        encode: F,
+    ) where
+        F: FnOnce(Option<Arc<Pusher>>, Vec<Sample>) -> Fut,
+        Fut: Future<Output = Result<Report, E>>,
+        E: Debug,
+    {
+        self.sync_spool(config);
+        let started = Instant::now();
+
+        // without credentials or TLS setup pushes would fail, so reports are kept for later
+        let pusher = self.pusher(config, url, headers);
+        let pushed = match pusher.as_ref() {
+            Some(pusher) => self.replay_spool(pusher, started).await,
+            None => false,
+        };
+
+        // no sense in waiting for retries if replay has just failed
+        let pusher = if pushed { pusher } else { None };
+        let report = match encode(pusher.clone(), self.self_metrics.samples()).await {
+            Ok(report) => report,
+            Err(err) => {
+                error!("Unable to encode report, {:?}", err);
+                self.self_metrics.dropped_payloads.inc();
+                return;
+            }
+        };
+        match report {
+            Report::Streamed(parts) => {
+                self.settle_parts(pusher.as_deref(), parts, config.compression, started)
+                    .await
+            }
+            Report::Encoded(payloads) => {
+                self.deliver(
+                    pusher,
+                    payloads,
+                    config.compression,
+                    config.max_parallel_pushes,
+                    started,
+                )
+                .await
+            }
+        }
+        self.update_spool_metrics();
+    }
+
+    /// oldest spooled payload, unreadable ones and ones of another protocol are dropped
+    pub fn spooled(&mut self) -> Option<(Vec<u8>, Compression)> {
+        while let Some((_, spool)) = self.spool.as_mut() {
+            match spool.oldest()? {
+                Ok((payload, encoding)) if encoding.kind == self.kind => {
+                    return Some((payload, encoding.compression))
+                }
+                Ok((_, encoding)) => error!(
+                    "Dropping spooled {:?} payload, {} is exported as {:?}",
+                    encoding.kind, self.target, self.kind
+                ),
+                Err(err) => error!("Unable to read spooled payload, dropping it, {:?}", err),
+            }
+            self.self_metrics.dropped_payloads.inc();
+            if let Err(err) = spool.pop() {
+                error!("Unable to remove spooled payload, {:?}", err);
+                return None;
+            }
+        }
+        None
+    }
+
+    /// removes oldest spooled payload once it's handled, false if it can't be removed
+    pub fn pop_spooled(&mut self) -> bool {
+        if let Some((_, spool)) = self.spool.as_mut() {
+            if let Err(err) = spool.pop() {
+                error!("Unable to remove replayed payload, {:?}", err);
+                return false;
+            }
+        }
+        true
+    }
+
+    /// false -> target is still unreachable, spooled payloads are kept
+    async fn replay_spool(&mut self, pusher: &Pusher, started: Instant) -> bool {
+        while let Some((payload, compression)) = self.spooled() {
+            let payload = Payload::Memory(Bytes::from(payload));
+            match pusher
+                .push_with_retry(&payload, compression, started, &mut self.self_metrics)
+                .await
+            {
+                Ok(()) => {}
+                // target will never accept it, it would block the rest of spool
+                Err(err) if !err.is_retryable() => {
+                    error!("Dropping spooled payload rejected by {}", self.target);
+                    self.self_metrics.dropped_payloads.inc();
+                }
+                Err(_) => return false,
+            }
+            if !self.pop_spooled() {
+                break;
+            }
+        }
+
+        true
+    }
+
+    /// retries pushed part if needed, part that wasn't pushed (`first` is None) goes to spool
+    async fn settle(
+        &mut self,
+        pusher: Option<&Pusher>,
+        payload: Payload,
+        first: Option<Result<(), PushError>>,
+        compression: Compression,
+        started: Instant,
+    ) {
+        let (first, pusher) = match (first, pusher) {
+            (Some(first), Some(pusher)) => (first, pusher),
+            _ => {
+                self.spool_payload(payload, compression);
+                return;
+            }
+        };
+        match pusher
+            .retry(
+                first,
+                &payload,
+                compression,
+                started,
+                &mut self.self_metrics,
+            )
+            .await
+        {
+            Ok(()) => {}
+            Err(err) if !err.is_retryable() => {
+                error!("Dropping payload rejected by {}", self.target);
+                self.self_metrics.dropped_payloads.inc();
+            }
+            Err(_) => self.spool_payload(payload, compression),
+        }
+    }
+
+    /// waits for first attempts of report parts, then retries or spools them one by one
+    /// parts left once retry deadline of the report has passed are spooled after first attempt
+    async fn settle_parts(
+        &mut self,
+        pusher: Option<&Pusher>,
+        parts: Vec<SentPart>,
+        compression: Compression,
+        started: Instant,
+    ) {
+        if parts.len() > 1 {
+            info!("Report is split into {} parts", parts.len());
+        }
+        for part in parts {
+            let first = match part.push {
+                Some(push) => Some(
+                    push.await
+                        .unwrap_or_else(|err| Err(PushError::Aborted(err))),
+                ),
+                None => None,
+            };
+            self.settle(pusher, part.payload, first, compression, started)
+                .await;
+        }
+    }
+
+    /// pushes already encoded payloads, at most `max_parallel` at once
+    /// payloads are spooled without pushing if `pusher` is None
+    async fn deliver(
+        &mut self,
+        pusher: Option<Arc<Pusher>>,
+        payloads: Vec<Bytes>,
+        compression: Compression,
+        max_parallel: usize,
+        started: Instant,
+    ) {
+        let slots = Arc::new(Semaphore::new(max_parallel));
+        let mut parts = Vec::new();
+        for payload in payloads {
+            let push = match pusher.clone() {
+                Some(pusher) => {
+                    let slot = slots.clone().acquire_owned().await.unwrap();
+                    let body = Body::from(payload.clone());
+                    Some(tokio::spawn(async move {
+                        let result = pusher.send(body, compression).await;
+                        drop(slot);
+                        result
+                    }))
+                }
+                None => None,
+            };
+            parts.push(SentPart {
+                payload: Payload::Memory(payload),
+                push,
+            });
+        }
+
+        self.settle_parts(pusher.as_deref(), parts, compression, started)
+            .await;
+    }
+
+    pub fn save_to_spool(&mut self, payload: &[u8], compression: Compression) {
+        let encoding = self.encoding(compression);
+        self.spool_with(|spool| spool.push(payload, encoding));
+    }
+
+    /// report parts are moved to spool rather than copied
+    fn spool_payload(&mut self, payload: Payload, compression: Compression) {
+        match payload {
+            Payload::Memory(payload) => self.save_to_spool(&payload, compression),
+            Payload::File(part) => {
+                let encoding = self.encoding(compression);
+                self.spool_with(|spool| spool.push_file(part.path(), encoding))
+            }
+        }
+    }
+
+    fn encoding(&self, compression: Compression) -> Encoding {
+        Encoding {
+            kind: self.kind,
+            compression,
+        }
+    }
+
+    fn spool_with<F>(&mut self, push: F)
+    where
+        F: FnOnce(&mut Spool) -> IOResult<u64>,
+    {
+        match self.spool.as_mut() {
+            Some((_, spool)) => match push(spool) {
+                Ok(dropped) => self.self_metrics.dropped_payloads.add(dropped),
+                Err(err) => {
+                    error!("Unable to spool payload, dropping it, {:?}", err);
+                    self.self_metrics.dropped_payloads.inc();
+                }
+            },
+            None => {
+                warn!("Spool is not configured, dropping payload");
+                self.self_metrics.dropped_payloads.inc();
+            }
+        }
+    }
+
+    /// called once report is done, so next report carries current spool state
+    pub fn update_spool_metrics(&mut self) {
+        if let Some((_, spool)) = self.spool.as_ref() {
+            self.self_metrics.spool_size_bytes.set(spool.size_bytes());
+            self.self_metrics.spool_payloads.set(spool.len() as u64);
+        } else {
+            self.self_metrics.spool_size_bytes.set(0);
+            self.self_metrics.spool_payloads.set(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::defs::{ExporterKind, ReporterConfig};
+    use crate::workers::registry::delivery::{Delivery, Report};
+    use hyper::body::Bytes;
+    use hyper::HeaderMap;
+    use std::net::TcpListener;
+    use std::time::{Duration, Instant};
+
+    #[tokio::test]
+    async fn test_push_report_spools_for_same_protocol() {
+        // nothing listens there once listener is dropped
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let dir = std::env::temp_dir().join(format!("palantir-delivery-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config: ReporterConfig = serde_yaml::from_str(&format!(
+            "remote_write_url: http://{}/api/v1/push\n\
+             retry:\n  max_attempts: 1\n\
+             spool:\n  path: {}\n  max_size_bytes: 1024",
+            address,
+            dir.display()
+        ))
+        .unwrap();
+        let url = config.remote_write_url.clone().unwrap();
+        let mut delivery = Delivery::new("rw".to_string(), ExporterKind::RemoteWrite);
+
+        delivery
+            .push_report(&config, url, HeaderMap::new(), |_, own| async move {
+                assert!(!own.is_empty());
+                Ok::<_, ()>(Report::Encoded(vec![Bytes::from_static(b"payload")]))
+            })
+            .await;
+
+        assert_eq!(delivery.self_metrics.spool_payloads.get(), 1);
+        // spool is taken over by a target of another protocol, it can't replay the payload
+        let mut other = Delivery::new("vm".to_string(), ExporterKind::VmImport);
+        other.sync_spool(&config);
+        assert_eq!(other.spooled(), None);
+        assert_eq!(other.self_metrics.dropped_payloads.get(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_rebuilt_on_settings_change() {
+        let mut config: ReporterConfig =
+            serde_yaml::from_str("remote_write_url: http://localhost:9009/api/v1/push").unwrap();
+        let url = config.remote_write_url.clone().unwrap();
+        let mut delivery = Delivery::new("rw".to_string(), ExporterKind::RemoteWrite);
+        let connect_timeout = |delivery: &Delivery| {
+            delivery
+                .client
+                .as_ref()
+                .map(|(settings, _)| settings.connect_timeout)
+        };
+
+        assert!(delivery
+            .pusher(&config, url.clone(), HeaderMap::new())
+            .is_some());
+        assert_eq!(connect_timeout(&delivery), Some(config.connect_timeout()));
+
+        config.connect_timeout_ms = Some(100);
+        assert!(delivery
+            .pusher(&config, url.clone(), HeaderMap::new())
+            .is_some());
+        assert_eq!(connect_timeout(&delivery), Some(Duration::from_millis(100)));
+
+        // client that can't be built doesn't replace the working one
+        config.tls = serde_yaml::from_str("ca_file: /nonexistent/ca.pem").unwrap();
+        assert!(delivery.pusher(&config, url, HeaderMap::new()).is_none());
+        assert_eq!(connect_timeout(&delivery), Some(Duration::from_millis(100)));
+    }
+
+    #[tokio::test]
+    async fn test_push_report_shares_retry_deadline() {
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let dir = std::env::temp_dir().join(format!("palantir-deadline-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config: ReporterConfig = serde_yaml::from_str(&format!(
+            "remote_write_url: http://{}/api/v1/push\n\
+             period_seconds: 1\n\
+             retry:\n  max_attempts: 10\n  initial_backoff_ms: 400\n  max_backoff_ms: 400\n\
+             spool:\n  path: {}",
+            address,
+            dir.display()
+        ))
+        .unwrap();
+        let url = config.remote_write_url.clone().unwrap();
+        let mut delivery = Delivery::new("rw".to_string(), ExporterKind::RemoteWrite);
+        let started = Instant::now();
+
+        delivery
+            .push_report(&config, url, HeaderMap::new(), |_, _| async move {
+                let part = Bytes::from_static(b"payload");
+                Ok::<_, ()>(Report::Encoded(vec![part.clone(), part]))
+            })
+            .await;
+
+        // second part isn't given a period of retries of its own
+        assert!(started.elapsed() < Duration::from_millis(1200));
+        assert_eq!(delivery.self_metrics.spool_payloads.get(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,101 @@
+use crate::config::defs::Compression;
+use flate2::write::GzEncoder;
+use std::io::{Result as IOResult, Write};
+
+/// compresses report as it's being serialized
+/// encoded output is taken in chunks, so the whole report is never kept uncompressed
+pub enum Encoder {
+    Identity(Vec<u8>),
+    Gzip(GzEncoder<Vec<u8>>),
+    Zstd(zstd::Encoder<'static, Vec<u8>>),
+}
+
+impl Encoder {
+    pub fn new(compression: Compression) -> IOResult<Self> {
+        Ok(match compression {
+            Compression::None => Encoder::Identity(Vec::new()),
+            Compression::Gzip => {
+                Encoder::Gzip(GzEncoder::new(Vec::new(), flate2::Compression::default()))
+            }
+            Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(Vec::new(), 0)?),
+        })
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> IOResult<()> {
+        match self {
+            Encoder::Identity(output) => output.write_all(data),
+            Encoder::Gzip(encoder) => encoder.write_all(data),
+            Encoder::Zstd(encoder) => encoder.write_all(data),
+        }
+    }
+
+    /// size of encoded output not taken yet
+    pub fn pending(&self) -> usize {
+        match self {
+            Encoder::Identity(output) => output.len(),
+            Encoder::Gzip(encoder) => encoder.get_ref().len(),
+            Encoder::Zstd(encoder) => encoder.get_ref().len(),
+        }
+    }
+
+    /// takes encoded output produced so far
+    pub fn take(&mut self) -> Vec<u8> {
+        match self {
+            Encoder::Identity(output) => std::mem::take(output),
+            Encoder::Gzip(encoder) => std::mem::take(encoder.get_mut()),
+            Encoder::Zstd(encoder) => std::mem::take(encoder.get_mut()),
+        }
+    }
+
+    /// flushes compressor, returns the rest of encoded output
+    /// nothing should be written after
+    pub fn finish(&mut self) -> IOResult<Vec<u8>> {
+        match self {
+            Encoder::Identity(_) => {}
+            Encoder::Gzip(encoder) => encoder.try_finish()?,
+            Encoder::Zstd(encoder) => encoder.do_finish()?,
+        }
+        Ok(self.take())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::defs::Compression;
+    use crate::workers::registry::encoder::Encoder;
+    use std::io::Read;
+
+    fn encode(compression: Compression, rows: &[String]) -> Vec<u8> {
+        let mut encoder = Encoder::new(compression).unwrap();
+        let mut encoded = Vec::new();
+        for row in rows {
+            encoder.write(row.as_bytes()).unwrap();
+            if encoder.pending() > 128 {
+                encoded.extend(encoder.take());
+            }
+        }
+        encoded.extend(encoder.finish().unwrap());
+        encoded
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let rows: Vec<String> = (0..1000)
+            .map(|i| format!("palantir_apm_count{{generation=\"1\"}} {}\n", i))
+            .collect();
+        let expected = rows.concat().into_bytes();
+
+        let identity = encode(Compression::None, &rows);
+        let gzip = encode(Compression::Gzip, &rows);
+        let zstd = encode(Compression::Zstd, &rows);
+
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(&gzip[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, expected);
+        assert_eq!(zstd::decode_all(&zstd[..]).unwrap(), expected);
+        assert_eq!(identity, expected);
+        assert!(gzip.len() < expected.len() / 4);
+    }
+}
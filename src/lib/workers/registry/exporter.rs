@@ -0,0 +1,43 @@
+use crate::config::defs::ReporterConfig;
+use crate::metrics::histogram::metric::Histogram;
+use crate::workers::registry::hc::HistogramCollection;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// state of collections matching target filter, taken at once at report time
+#[derive(Clone)]
+pub struct Snapshot {
+    pub timestamp: SystemTime,
+    pub handle_time: Histogram,
+    /// shared with registry, see `Reporter::snapshot`
+    pub collections: Vec<Arc<HistogramCollection>>,
+}
+
+impl Snapshot {
+    /// every point of report is stamped with it
+    pub fn timestamp_ms(&self) -> u128 {
+        self.timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis()
+    }
+}
+
+pub type ExportFuture<'a> = Pin<Box<dyn Future<Output = ()> + 'a>>;
+
+/// encodes snapshots and delivers them to a target
+/// failures are handled by exporter itself (retries, spool), reporter just moves on to next report
+/// exporters run on a single thread, so they don't have to be `Send`
+pub trait Exporter {
+    /// `config` is target section as of this report, it may change between calls
+    fn export<'a>(
+        &'a mut self,
+        config: &'a ReporterConfig,
+        snapshot: &'a Snapshot,
+    ) -> ExportFuture<'a>;
+}
+
+/// builds exporter of a target when its reporter starts, see `apm::run_registry`
+pub type ExporterFactory = Box<dyn Fn(&str, &ReporterConfig) -> Box<dyn Exporter> + Send>;
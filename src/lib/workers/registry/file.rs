@@ -0,0 +1,339 @@
+use crate::config::defs::{Compression, FileConfig, FileFormat, ReporterConfig};
+use crate::metrics::sample::Sample;
+use crate::metrics::tag::Tag;
+use crate::metrics::traits::PrometheusMetric;
+use crate::workers::registry::encoder::Encoder;
+use crate::workers::registry::exporter::{ExportFuture, Exporter, Snapshot};
+use crate::workers::registry::self_metrics::SelfMetrics;
+use log::{error, info};
+use std::collections::BTreeMap;
+use std::io::Result as IOResult;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+/// report files are told from anything else in the directory by it
+const FILE_PREFIX: &str = "palantir-";
+const METRIC_NAME_LABEL: &str = "__name__";
+
+/// e.g. `prom.gz`, compressed reports are appended as separate gzip members or zstd frames
+fn extension(format: FileFormat, compression: Compression) -> String {
+    let format = match format {
+        FileFormat::Prometheus => "prom",
+        FileFormat::Jsonl => "jsonl",
+    };
+    match compression {
+        Compression::None => format.to_string(),
+        Compression::Gzip => format!("{}.gz", format),
+        Compression::Zstd => format!("{}.zst", format),
+    }
+}
+
+/// creation time in milliseconds is the name, so files sort chronologically
+fn file_time(path: &Path) -> Option<u64> {
+    let name = path.file_name()?.to_str()?;
+    let stem = name.strip_prefix(FILE_PREFIX)?.split('.').next()?;
+    stem.parse().ok()
+}
+
+/// extra labels win over tags with the same name
+fn with_extra_labels(mut sample: Sample, extra_labels: &BTreeMap<String, String>) -> Sample {
+    sample
+        .tags
+        .retain(|tag| !extra_labels.contains_key(&tag.key));
+    sample
+        .tags
+        .extend(extra_labels.iter().map(|(key, value)| Tag {
+            key: key.clone(),
+            value: value.clone(),
+        }));
+    sample
+}
+
+fn prometheus_row(sample: &Sample, timestamp_ms: u128) -> String {
+    sample.to_exposition_at(timestamp_ms)
+}
+
+/// `{"metric":{"__name__":...},"values":[...],"timestamps":[...]}`
+fn json_row(sample: &Sample, timestamp_ms: u128) -> String {
+    let mut labels: BTreeMap<&str, &str> = sample
+        .tags
+        .iter()
+        .map(|tag| (tag.key.as_str(), tag.value.as_str()))
+        .collect();
+    labels.insert(METRIC_NAME_LABEL, &sample.name);
+    let row = serde_json::json!({
+        "metric": labels,
+        "values": [sample.value],
+        "timestamps": [timestamp_ms as u64],
+    });
+    format!("{}\n", row)
+}
+
+/// report files in `dir`, oldest first
+async fn report_files(dir: &Path) -> IOResult<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if let Some(time) = file_time(&path) {
+            found.push((time, path));
+        }
+    }
+    found.sort();
+    Ok(found.into_iter().map(|(_, path)| path).collect())
+}
+
+/// failures are only logged, report still goes to the new file
+async fn remove_old_files(config: &FileConfig) {
+    let files = match report_files(&config.dir).await {
+        Ok(files) => files,
+        Err(err) => {
+            error!("Unable to list report files in {:?}, {:?}", config.dir, err);
+            return;
+        }
+    };
+    let excess = files.len().saturating_sub(config.max_files as usize);
+    for path in &files[..excess] {
+        match fs::remove_file(path).await {
+            Ok(()) => info!("Removed old report file {:?}", path),
+            Err(err) => error!("Unable to remove report file {:?}, {:?}", path, err),
+        }
+    }
+}
+
+struct CurrentFile {
+    path: PathBuf,
+    file: fs::File,
+    size_bytes: u64,
+    opened: Instant,
+}
+
+/// writes reports to local files for offline collection or diffing
+/// rows are timestamped, so files can be imported to VictoriaMetrics as they are
+pub struct FileExporter {
+    target: String,
+    self_metrics: SelfMetrics,
+    current: Option<CurrentFile>,
+}
+
+impl FileExporter {
+    pub fn new(target: String) -> Self {
+        Self {
+            self_metrics: SelfMetrics::new(&target),
+            target,
+            current: None,
+        }
+    }
+
+    /// histograms are written as `le` histograms, as scrape endpoint serves them
+    fn report(&self, config: &ReporterConfig, format: FileFormat, snapshot: &Snapshot) -> String {
+        let timestamp_ms = snapshot.timestamp_ms();
+        let mut samples = self.self_metrics.samples();
+        samples.extend(snapshot.handle_time.samples());
+        for hc in snapshot.collections.iter() {
+            samples.extend(hc.samples());
+        }
+
+        let mut report = String::new();
+        for sample in samples {
+            let sample = with_extra_labels(sample, &config.extra_labels);
+            report.push_str(&match format {
+                FileFormat::Prometheus => prometheus_row(&sample, timestamp_ms),
+                FileFormat::Jsonl => json_row(&sample, timestamp_ms),
+            });
+        }
+        report
+    }
+
+    /// current file is kept while it's in the right place, has room for `size` bytes and isn't too old
+    fn should_rotate(&self, config: &FileConfig, extension: &str, size: u64) -> bool {
+        let current = match &self.current {
+            Some(current) => current,
+            None => return true,
+        };
+        let moved = current.path.parent() != Some(config.dir.as_path())
+            || !current
+                .path
+                .to_string_lossy()
+                .ends_with(&format!(".{}", extension));
+        let full = current.size_bytes > 0 && current.size_bytes + size > config.max_file_bytes;
+        let old = config.rotate_seconds > 0
+            && current.opened.elapsed() >= Duration::from_secs(config.rotate_seconds);
+        moved || full || old
+    }
+
+    /// starts a new file, then removes the oldest ones beyond `max_files`
+    async fn rotate(&mut self, config: &FileConfig, extension: &str) -> IOResult<()> {
+        self.current = None;
+        fs::create_dir_all(&config.dir).await?;
+
+        let mut time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let (path, file) = loop {
+            let path = config
+                .dir
+                .join(format!("{}{:020}.{}", FILE_PREFIX, time, extension));
+            match fs::OpenOptions::new()
+                .append(true)
+                .create_new(true)
+                .open(&path)
+                .await
+            {
+                Ok(file) => break (path, file),
+                // rotated twice within a millisecond
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => time += 1,
+                Err(err) => return Err(err),
+            }
+        };
+        info!("Writing reports of {} to {:?}", self.target, path);
+        self.current = Some(CurrentFile {
+            path,
+            file,
+            size_bytes: 0,
+            opened: Instant::now(),
+        });
+
+        remove_old_files(config).await;
+        Ok(())
+    }
+
+    /// file is abandoned on failure, so the next report starts a new one
+    async fn write(
+        &mut self,
+        config: &FileConfig,
+        extension: &str,
+        payload: &[u8],
+    ) -> IOResult<()> {
+        let size = payload.len() as u64;
+        if self.should_rotate(config, extension, size) {
+            self.rotate(config, extension).await?;
+        }
+        // rotate either sets current file or fails
+        let current = self.current.as_mut().unwrap();
+        let result = async {
+            current.file.write_all(payload).await?;
+            current.file.flush().await
+        }
+        .await;
+        match result {
+            Ok(()) => current.size_bytes += size,
+            Err(_) => self.current = None,
+        }
+        result
+    }
+
+    async fn save(&mut self, config: &ReporterConfig, snapshot: &Snapshot) {
+        // config validation checks that file section is there
+        let file_config = match config.file.as_ref() {
+            Some(file_config) => file_config,
+            None => return,
+        };
+        let extension = extension(file_config.format, config.compression);
+        let report = self.report(config, file_config.format, snapshot);
+
+        let payload = Encoder::new(config.compression).and_then(|mut encoder| {
+            encoder.write(report.as_bytes())?;
+            encoder.finish()
+        });
+        let result = match payload {
+            Ok(payload) => self.write(file_config, &extension, &payload).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            error!("Unable to write report to {:?}, {:?}", file_config.dir, err);
+            self.self_metrics.dropped_payloads.inc();
+        }
+    }
+}
+
+impl Exporter for FileExporter {
+    fn export<'a>(
+        &'a mut self,
+        config: &'a ReporterConfig,
+        snapshot: &'a Snapshot,
+    ) -> ExportFuture<'a> {
+        Box::pin(self.save(config, snapshot))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::defs::{FileConfig, FileFormat};
+    use crate::metrics::sample::Sample;
+    use crate::metrics::tag::Tag;
+    use crate::workers::registry::file::{
+        json_row, prometheus_row, report_files, with_extra_labels, FileExporter,
+    };
+    use std::collections::BTreeMap;
+
+    fn sample() -> Sample {
+        let tags = vec![Tag::new("pod", "web-1"), Tag::new("le", "255")];
+        let mut extra_labels = BTreeMap::new();
+        extra_labels.insert("pod".to_string(), "web-2".to_string());
+        with_extra_labels(Sample::new("palantir_apm_bucket", tags, 3), &extra_labels)
+    }
+
+    #[test]
+    fn test_rows() {
+        assert_eq!(
+            prometheus_row(&sample(), 1000),
+            "palantir_apm_bucket{le=\"255\",pod=\"web-2\"} 3 1000\n"
+        );
+        assert_eq!(
+            json_row(&sample(), 1000),
+            "{\"metric\":{\"__name__\":\"palantir_apm_bucket\",\"le\":\"255\",\"pod\":\"web-2\"},\
+             \"timestamps\":[1000],\"values\":[3]}\n"
+        );
+    }
+
+    #[test]
+    fn test_prometheus_row_escapes_labels() {
+        let sample = Sample::new(
+            "palantir_apm_count",
+            vec![Tag::new("action_name", "a\"b\\\nc 1")],
+            3,
+        );
+
+        assert_eq!(
+            prometheus_row(&sample, 1000),
+            "palantir_apm_count{action_name=\"a\\\"b\\\\\\nc 1\"} 3 1000\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rotation_and_retention() {
+        let dir = std::env::temp_dir().join(format!("palantir-files-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // not a report file, retention leaves it alone
+        std::fs::write(dir.join("notes.txt"), b"keep").unwrap();
+        let config = FileConfig {
+            dir: dir.clone(),
+            format: FileFormat::Prometheus,
+            max_file_bytes: 10,
+            rotate_seconds: 0,
+            max_files: 2,
+        };
+        let mut exporter = FileExporter::new("files".to_string());
+
+        for payload in ["aaaa\n", "bbbb\n", "cccc\n", "dddd\n", "eeee\n", "ffff\n"] {
+            exporter
+                .write(&config, "prom", payload.as_bytes())
+                .await
+                .unwrap();
+        }
+
+        let files = report_files(&dir).await.unwrap();
+        let contents: Vec<String> = files
+            .iter()
+            .map(|path| std::fs::read_to_string(path).unwrap())
+            .collect();
+        assert_eq!(contents, vec!["cccc\ndddd\n", "eeee\nffff\n"]);
+        assert!(dir.join("notes.txt").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,450 @@
+use crate::config::defs::{Compression, ExporterKind, ReporterConfig};
+use crate::constants as c;
+use crate::metrics::histogram::metric::Histogram;
+use crate::metrics::sample::Sample;
+use crate::metrics::tag::Tag;
+use crate::metrics::traits::PrometheusMetric;
+use crate::util::template::PathTemplate;
+use crate::workers::registry::delivery::Delivery;
+use crate::workers::registry::exporter::{ExportFuture, Exporter, Snapshot};
+use crate::workers::registry::push::next_retry;
+use log::{error, info, warn};
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result as IOResult};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+/// path node of tag that is missing or empty
+const UNKNOWN_NODE: &str = "unknown";
+
+/// placeholders may use short names of agent's own tags, other names are looked up as is
+fn tag_name(placeholder: &str) -> &str {
+    match placeholder {
+        "realm" => c::REALM_TAG_NAME,
+        "application" => c::APPLICATION_TAG_NAME,
+        "application_hash" => c::APPLICATION_HASH_TAG_NAME,
+        "action_kind" => c::ACTION_KIND_TAG_NAME,
+        "action_name" => c::ACTION_NAME_TAG_NAME,
+        "span" => c::ACTION_SPAN_TAG_NAME,
+        other => other,
+    }
+}
+
+/// dots would split the node, so anything but letters, digits, `-` and `_` becomes `_`
+fn sanitize(value: &str) -> String {
+    if value.is_empty() {
+        return UNKNOWN_NODE.to_string();
+    }
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// client histograms are placed by template, tags win over extra labels
+fn histogram_path(
+    template: &PathTemplate,
+    tags: &[Tag],
+    extra_labels: &BTreeMap<String, String>,
+) -> String {
+    template.render(|name| {
+        let key = tag_name(name);
+        let value = tags
+            .iter()
+            .find(|tag| tag.key == key)
+            .map(|tag| tag.value.as_str())
+            .or_else(|| extra_labels.get(name).map(String::as_str))
+            .unwrap_or_default();
+        sanitize(value)
+    })
+}
+
+/// agent's own metrics are named after metric, tag values become nodes below it
+fn own_path(name: &str, tags: &[Tag]) -> String {
+    let mut path = sanitize(name);
+    for tag in tags {
+        path.push('.');
+        path.push_str(&sanitize(&tag.value));
+    }
+    path
+}
+
+/// `count`, `sum` and cumulative `le_<bound>` buckets (`le_inf` for the last one) below `path`
+/// histograms sharing the path are summed, Graphite would keep only the last point otherwise
+fn histogram_lines(path: &str, histograms: &[&Histogram], timestamp_s: u128) -> Vec<String> {
+    let mut lines = Vec::with_capacity(32);
+    let count: u64 = histograms.iter().map(|h| h.count()).sum();
+    let sum: u64 = histograms.iter().map(|h| h.sum()).sum();
+    lines.push(format!("{}.count {} {}\n", path, count, timestamp_s));
+    lines.push(format!("{}.sum {} {}\n", path, sum, timestamp_s));
+    let mut buckets: Vec<(u64, u64)> = Vec::new();
+    for histogram in histograms {
+        for (i, (upper, count)) in histogram.bucket_counts().enumerate() {
+            match buckets.get_mut(i) {
+                Some((_, total)) => *total += count,
+                None => buckets.push((upper, count)),
+            }
+        }
+    }
+    let mut cumulative = 0u64;
+    for (upper, count) in buckets {
+        cumulative += count;
+        let bucket = if upper == u64::MAX {
+            "inf".to_string()
+        } else {
+            upper.to_string()
+        };
+        lines.push(format!(
+            "{}.le_{} {} {}\n",
+            path, bucket, cumulative, timestamp_s
+        ));
+    }
+    lines
+}
+
+fn sample_line(sample: &Sample, timestamp_s: u128) -> String {
+    format!(
+        "{} {} {}\n",
+        own_path(&sample.name, &sample.tags),
+        sample.value,
+        timestamp_s
+    )
+}
+
+/// splits lines into payloads of at most `max_payload_bytes`, a longer line gets a payload of its own
+fn split_lines(lines: Vec<String>, max_payload_bytes: usize) -> Vec<String> {
+    let mut payloads = Vec::new();
+    let mut current = String::new();
+    for line in lines {
+        if !current.is_empty() && current.len() + line.len() > max_payload_bytes {
+            payloads.push(std::mem::take(&mut current));
+        }
+        current.push_str(&line);
+    }
+    if !current.is_empty() {
+        payloads.push(current);
+    }
+    payloads
+}
+
+/// Graphite never writes back, so readable socket means peer has closed (or reset) it
+fn is_closed(stream: &TcpStream) -> bool {
+    let mut buf = [0u8; 1];
+    match stream.try_read(&mut buf) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(err) => err.kind() != ErrorKind::WouldBlock,
+    }
+}
+
+/// writes Graphite plaintext protocol over a long-lived TCP connection
+/// connection is re-established when it drops, reports that couldn't be written are spooled
+pub struct GraphiteExporter {
+    delivery: Delivery,
+    /// (address, stream), address may change on reload
+    connection: Option<(String, TcpStream)>,
+}
+
+impl GraphiteExporter {
+    pub fn new(target: String) -> Self {
+        Self {
+            delivery: Delivery::new(target, ExporterKind::Graphite),
+            connection: None,
+        }
+    }
+
+    /// report lines, split by `max_payload_bytes` if it's set
+    fn report(
+        &self,
+        config: &ReporterConfig,
+        template: &PathTemplate,
+        snapshot: &Snapshot,
+    ) -> Vec<String> {
+        let timestamp_s = snapshot.timestamp_ms() / 1000;
+        let mut lines = Vec::new();
+        for sample in self.delivery.self_metrics.samples() {
+            lines.push(sample_line(&sample, timestamp_s));
+        }
+        let handle_time = &snapshot.handle_time;
+        let path = own_path(handle_time.name(), handle_time.tags());
+        lines.extend(histogram_lines(&path, &[handle_time], timestamp_s));
+
+        let mut paths: BTreeMap<String, Vec<&Histogram>> = BTreeMap::new();
+        for hc in snapshot.collections.iter() {
+            for histogram in hc.histograms() {
+                let path = histogram_path(template, histogram.tags(), &config.extra_labels);
+                paths.entry(path).or_default().push(histogram);
+            }
+        }
+        let shared = paths.values().filter(|group| group.len() > 1).count();
+        if shared > 0 {
+            warn!(
+                "{} Graphite paths of {} are shared by several histograms, their points are summed, \
+                 add placeholders to graphite_path_template to tell them apart",
+                shared, self.delivery.target
+            );
+        }
+        for (path, histograms) in paths.iter() {
+            lines.extend(histogram_lines(path, histograms, timestamp_s));
+        }
+
+        let max_payload_bytes = config
+            .max_payload_bytes
+            .map(|limit| limit as usize)
+            .unwrap_or(usize::MAX);
+        split_lines(lines, max_payload_bytes)
+    }
+
+    /// open connection is reused unless peer has closed it or address has changed
+    async fn connect(&mut self, config: &ReporterConfig) -> IOResult<&mut TcpStream> {
+        let address = config.graphite_address.clone().unwrap_or_default();
+        let reusable = match &self.connection {
+            Some((connected, stream)) => *connected == address && !is_closed(stream),
+            None => false,
+        };
+        if !reusable {
+            if self.connection.take().is_some() {
+                info!("Reconnecting to {}", self.delivery.target);
+            }
+            let timeout = config.connect_timeout();
+            let stream = tokio::time::timeout(timeout, TcpStream::connect(address.as_str()))
+                .await
+                .map_err(|_| Error::new(ErrorKind::TimedOut, "connect timed out"))??;
+            stream.set_nodelay(true)?;
+            self.connection = Some((address, stream));
+        }
+        Ok(&mut self.connection.as_mut().unwrap().1)
+    }
+
+    /// connection is dropped on failure, so the next write starts over with a new one
+    async fn write(&mut self, config: &ReporterConfig, payload: &[u8]) -> IOResult<()> {
+        let timeout = config.request_timeout();
+        let write = async {
+            let stream = self.connect(config).await?;
+            stream.write_all(payload).await?;
+            stream.flush().await
+        };
+        let result = tokio::time::timeout(timeout, write)
+            .await
+            .unwrap_or_else(|_| Err(Error::new(ErrorKind::TimedOut, "write timed out")));
+        if result.is_err() {
+            self.connection = None;
+        }
+        result
+    }
+
+    /// there is no response in plaintext protocol, every failure is worth a retry
+    /// `started` is when the report began, so all of its payloads share one retry deadline
+    async fn write_with_retry(
+        &mut self,
+        config: &ReporterConfig,
+        payload: &[u8],
+        started: Instant,
+    ) -> IOResult<()> {
+        let max_retry_time = Duration::from_secs(config.period_seconds);
+        let mut attempt = 1;
+        loop {
+            let err = match self.write(config, payload).await {
+                Ok(()) => {
+                    self.delivery.self_metrics.push_success.inc();
+                    return Ok(());
+                }
+                Err(err) => err,
+            };
+            self.delivery.self_metrics.push_retryable.inc();
+            let delay = match next_retry(&config.retry, attempt, started.elapsed(), max_retry_time)
+            {
+                Some(delay) => delay,
+                None => {
+                    error!(
+                        "Write to {} failed after {} attempts, {}",
+                        self.delivery.target, attempt, err
+                    );
+                    self.delivery.self_metrics.push_failures.inc();
+                    return Err(err);
+                }
+            };
+            warn!(
+                "Write attempt {} to {} failed, retrying in {}ms, {}",
+                attempt,
+                self.delivery.target,
+                delay.as_millis(),
+                err
+            );
+            self.delivery.self_metrics.push_retries.inc();
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    async fn push(&mut self, config: &ReporterConfig, snapshot: &Snapshot) {
+        self.delivery.sync_spool(config);
+        let template = match PathTemplate::parse(&config.graphite_path_template) {
+            Ok(template) => template,
+            // config validation checks that template is OK
+            Err(err) => {
+                error!("Invalid graphite path template, {}", err);
+                return;
+            }
+        };
+
+        // spooled reports go first, so points are written in order
+        let started = Instant::now();
+        let mut connected = true;
+        while let Some((payload, _)) = self.delivery.spooled() {
+            if self
+                .write_with_retry(config, &payload, started)
+                .await
+                .is_err()
+            {
+                connected = false;
+                break;
+            }
+            if !self.delivery.pop_spooled() {
+                break;
+            }
+        }
+
+        // rewritten points just overwrite the ones that got through before connection dropped
+        for payload in self.report(config, &template, snapshot) {
+            connected = connected
+                && self
+                    .write_with_retry(config, payload.as_bytes(), started)
+                    .await
+                    .is_ok();
+            if !connected {
+                self.delivery
+                    .save_to_spool(payload.as_bytes(), Compression::None);
+            }
+        }
+        self.delivery.update_spool_metrics();
+    }
+}
+
+impl Exporter for GraphiteExporter {
+    fn export<'a>(
+        &'a mut self,
+        config: &'a ReporterConfig,
+        snapshot: &'a Snapshot,
+    ) -> ExportFuture<'a> {
+        Box::pin(self.push(config, snapshot))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::defs::ReporterConfig;
+    use crate::constants as c;
+    use crate::metrics::histogram::metric::Histogram;
+    use crate::metrics::tag::Tag;
+    use crate::util::template::PathTemplate;
+    use crate::workers::registry::graphite::{
+        histogram_lines, histogram_path, split_lines, GraphiteExporter,
+    };
+    use std::collections::BTreeMap;
+    use std::time::{Duration, Instant};
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_histogram_path() {
+        let template = PathTemplate::parse("apm.{dc}.{realm}.{application}.{span}.{pod}").unwrap();
+        let tags = vec![
+            Tag::new(c::REALM_TAG_NAME, "prod"),
+            Tag::new(c::APPLICATION_TAG_NAME, ""),
+            Tag::new(c::ACTION_SPAN_TAG_NAME, "db.query users"),
+        ];
+        let mut extra_labels = BTreeMap::new();
+        extra_labels.insert("dc".to_string(), "eu-1".to_string());
+
+        let path = histogram_path(&template, &tags, &extra_labels);
+
+        assert_eq!(path, "apm.eu-1.prod.unknown.db_query_users.unknown");
+    }
+
+    #[test]
+    fn test_histogram_lines() {
+        let mut histogram = Histogram::new("hist".to_string(), Vec::new());
+        histogram.track(1);
+        histogram.track(300);
+
+        let lines = histogram_lines("apm.prod", &[&histogram], 1700000000);
+
+        assert_eq!(lines[0], "apm.prod.count 2 1700000000\n");
+        assert_eq!(lines[1], "apm.prod.sum 301 1700000000\n");
+        assert_eq!(lines[2], "apm.prod.le_255 1 1700000000\n");
+        assert_eq!(lines[3], "apm.prod.le_511 2 1700000000\n");
+        assert_eq!(lines.last().unwrap(), "apm.prod.le_inf 2 1700000000\n");
+    }
+
+    #[test]
+    fn test_histogram_lines_sum_shared_path() {
+        let mut first = Histogram::new("hist".to_string(), Vec::new());
+        first.track(1);
+        let mut second = Histogram::new("hist".to_string(), Vec::new());
+        second.track(300);
+        second.track(400);
+
+        let lines = histogram_lines("apm.prod", &[&first, &second], 1700000000);
+
+        assert_eq!(lines[0], "apm.prod.count 3 1700000000\n");
+        assert_eq!(lines[1], "apm.prod.sum 701 1700000000\n");
+        assert_eq!(lines[2], "apm.prod.le_255 1 1700000000\n");
+        assert_eq!(lines[3], "apm.prod.le_511 3 1700000000\n");
+        assert_eq!(lines.last().unwrap(), "apm.prod.le_inf 3 1700000000\n");
+    }
+
+    #[test]
+    fn test_split_lines() {
+        let lines = vec!["a 1 1\n", "b 2 1\n", "long.path 3 1\n", "c 4 1\n"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        let payloads = split_lines(lines, 12);
+
+        assert_eq!(
+            payloads,
+            vec!["a 1 1\nb 2 1\n", "long.path 3 1\n", "c 4 1\n"]
+        );
+        assert!(split_lines(Vec::new(), 12).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_after_drop() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config: ReporterConfig = serde_yaml::from_str(&format!(
+            "graphite_address: {}",
+            listener.local_addr().unwrap()
+        ))
+        .unwrap();
+        let mut exporter = GraphiteExporter::new("graphite".to_string());
+        let mut buf = [0u8; 16];
+
+        exporter
+            .write_with_retry(&config, b"a 1 1\n", Instant::now())
+            .await
+            .unwrap();
+        let (mut first, _) = listener.accept().await.unwrap();
+        let read = first.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..read], b"a 1 1\n");
+        drop(first);
+        // let FIN reach the agent
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        exporter
+            .write_with_retry(&config, b"b 2 2\n", Instant::now())
+            .await
+            .unwrap();
+        let (mut second, _) = listener.accept().await.unwrap();
+        let read = second.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..read], b"b 2 2\n");
+    }
+}
@@ -1,5 +1,7 @@
+use crate::config::defs::TargetFilter;
 use crate::constants as c;
 use crate::metrics::histogram::metric::Histogram;
+use crate::metrics::sample::Sample;
 use crate::metrics::tag::Tag;
 use crate::metrics::traits::PrometheusMetric;
 use crate::util::checksum::Checksum;
@@ -10,6 +12,7 @@ use palantir_proto::palantir::shared::measurement::Measurement as ProtoMeasureme
 use std::collections::HashMap;
 use std::time::Instant;
 
+#[derive(Clone)]
 pub struct HistogramCollection {
     tags: Vec<Tag>,
     metrics: HashMap<u64, Histogram>,
@@ -67,6 +70,27 @@ impl HistogramCollection {
         }
     }
 
+    pub fn histograms(&self) -> impl Iterator<Item = &Histogram> {
+        self.metrics.values()
+    }
+
+    fn tag_value(&self, key: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|tag| tag.key == key)
+            .map(|tag| tag.value.as_str())
+    }
+
+    /// empty filter list matches anything
+    pub fn matches(&self, filter: &TargetFilter) -> bool {
+        let allowed = |values: &[String], key: &str| {
+            let value = self.tag_value(key);
+            values.is_empty() || values.iter().any(|allowed| Some(allowed.as_str()) == value)
+        };
+        allowed(&filter.realms, c::REALM_TAG_NAME)
+            && allowed(&filter.applications, c::APPLICATION_TAG_NAME)
+    }
+
     pub fn process(&mut self, msg: ProtoMessage) {
         self.last_hit = Instant::now();
         match msg {
@@ -94,6 +118,15 @@ impl PrometheusMetric for HistogramCollection {
 
         result
     }
+
+    fn samples(&self) -> Vec<Sample> {
+        let mut result = Vec::new();
+        for histogram in self.metrics.values() {
+            result.extend(histogram.samples())
+        }
+
+        result
+    }
 }
 
 impl From<&ApmV1Action> for HistogramCollection {
@@ -128,3 +161,38 @@ impl From<&ApmV1Action> for HistogramCollection {
         HistogramCollection::new(tags)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::config::defs::TargetFilter;
+    use crate::constants as c;
+    use crate::metrics::tag::Tag;
+    use crate::workers::registry::hc::HistogramCollection;
+
+    fn collection(realm: &str, application: &str) -> HistogramCollection {
+        HistogramCollection::new(vec![
+            Tag {
+                key: c::REALM_TAG_NAME.to_string(),
+                value: realm.to_string(),
+            },
+            Tag {
+                key: c::APPLICATION_TAG_NAME.to_string(),
+                value: application.to_string(),
+            },
+        ])
+    }
+
+    #[test]
+    fn test_matches() {
+        let filter = TargetFilter {
+            realms: vec!["prod".to_string()],
+            applications: vec!["shop".to_string(), "billing".to_string()],
+        };
+
+        assert!(collection("prod", "shop").matches(&filter));
+        assert!(collection("prod", "billing").matches(&filter));
+        assert!(!collection("stage", "shop").matches(&filter));
+        assert!(!collection("prod", "search").matches(&filter));
+        assert!(collection("stage", "search").matches(&TargetFilter::default()));
+    }
+}
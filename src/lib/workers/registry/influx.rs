@@ -0,0 +1,247 @@
+use crate::config::defs::{ExporterKind, ReporterConfig};
+use crate::metrics::histogram::metric::Histogram;
+use crate::metrics::sample::Sample;
+use crate::metrics::tag::Tag;
+use crate::workers::registry::delivery::{Delivery, Report};
+use crate::workers::registry::exporter::{ExportFuture, Exporter, Snapshot};
+use crate::workers::registry::writer::{ReportWriter, SentPart};
+use hyper::HeaderMap;
+use std::collections::BTreeMap;
+use std::io::Result as IOResult;
+use url::Url;
+
+/// points are stamped with report time in milliseconds
+const PRECISION: &str = "ms";
+
+/// `precision` set in config is replaced, timestamps are always in milliseconds
+fn write_url(config: &ReporterConfig) -> Url {
+    // config validation checks that url is OK
+    let mut url = Url::parse(config.influx_write_url.as_deref().unwrap_or_default()).unwrap();
+    let query: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(key, _)| key != "precision")
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(query)
+        .append_pair("precision", PRECISION);
+    url
+}
+
+/// backslash-escapes `special` characters, line breaks can't be escaped and become escaped spaces
+fn escape(value: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\n' | '\r' => escaped.push_str("\\ "),
+            c if special.contains(&c) => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn escape_measurement(value: &str) -> String {
+    escape(value, &[',', ' '])
+}
+
+/// tag keys, tag values and field keys
+fn escape_key(value: &str) -> String {
+    escape(value, &[',', '=', ' '])
+}
+
+/// line protocol can't carry empty tag values, such tags are left out
+/// extra labels win over tags with the same name, tags are sorted as InfluxDB prefers
+fn series_key(measurement: &str, tags: &[Tag], extra_labels: &BTreeMap<String, String>) -> String {
+    let mut merged: BTreeMap<&str, &str> = tags
+        .iter()
+        .map(|tag| (tag.key.as_str(), tag.value.as_str()))
+        .collect();
+    merged.extend(
+        extra_labels
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str())),
+    );
+
+    let mut key = escape_measurement(measurement);
+    for (name, value) in merged {
+        if !value.is_empty() {
+            key.push_str(&format!(",{}={}", escape_key(name), escape_key(value)));
+        }
+    }
+    key
+}
+
+/// integer field, values past i64 range are clamped
+fn integer(value: u64) -> String {
+    format!("{}i", value.min(i64::MAX as u64))
+}
+
+/// cumulative buckets become `le_<bound>` fields (`le_inf` for the last one), along with `count` and `sum`
+fn histogram_line(histogram: &Histogram, extra_labels: &BTreeMap<String, String>) -> String {
+    let mut fields = Vec::with_capacity(32);
+    let mut cumulative = 0u64;
+    for (upper, count) in histogram.bucket_counts() {
+        cumulative += count;
+        let field = if upper == u64::MAX {
+            "le_inf".to_string()
+        } else {
+            format!("le_{}", upper)
+        };
+        fields.push(format!("{}={}", field, integer(cumulative)));
+    }
+    fields.push(format!("count={}", integer(histogram.count())));
+    fields.push(format!("sum={}", integer(histogram.sum())));
+
+    format!(
+        "{} {}",
+        series_key(histogram.name(), histogram.tags(), extra_labels),
+        fields.join(",")
+    )
+}
+
+/// counters and gauges, stored in `value` field
+fn sample_line(sample: &Sample, extra_labels: &BTreeMap<String, String>) -> String {
+    format!(
+        "{} value={}",
+        series_key(&sample.name, &sample.tags, extra_labels),
+        integer(sample.value)
+    )
+}
+
+/// a line per own metric and per histogram, every part is pushed as soon as it's started
+async fn stream_report(
+    config: &ReporterConfig,
+    mut writer: ReportWriter,
+    own: Vec<Sample>,
+    snapshot: &Snapshot,
+) -> IOResult<Vec<SentPart>> {
+    let suffix = format!(" {}\n", snapshot.timestamp_ms());
+    let extra_labels = &config.extra_labels;
+
+    for sample in own {
+        writer
+            .write_row(&sample_line(&sample, extra_labels), &suffix)
+            .await?;
+    }
+    writer
+        .write_row(
+            &histogram_line(&snapshot.handle_time, extra_labels),
+            &suffix,
+        )
+        .await?;
+    for hc in snapshot.collections.iter() {
+        for histogram in hc.histograms() {
+            writer
+                .write_row(&histogram_line(histogram, extra_labels), &suffix)
+                .await?;
+        }
+    }
+
+    writer.finish().await
+}
+
+/// writes InfluxDB line protocol to v1 `/write` or v2 `/api/v2/write`
+/// a point per histogram: measurement is metric name, tags are tags, buckets, count and sum are fields
+pub struct InfluxExporter {
+    delivery: Delivery,
+}
+
+impl InfluxExporter {
+    pub fn new(target: String) -> Self {
+        Self {
+            delivery: Delivery::new(target, ExporterKind::Influx),
+        }
+    }
+
+    async fn push(&mut self, config: &ReporterConfig, snapshot: &Snapshot) {
+        let url = write_url(config).to_string();
+        self.delivery
+            .push_report(config, url, HeaderMap::new(), |pusher, own| async move {
+                stream_report(config, ReportWriter::new(config, pusher), own, snapshot)
+                    .await
+                    .map(Report::Streamed)
+            })
+            .await;
+    }
+}
+
+impl Exporter for InfluxExporter {
+    fn export<'a>(
+        &'a mut self,
+        config: &'a ReporterConfig,
+        snapshot: &'a Snapshot,
+    ) -> ExportFuture<'a> {
+        Box::pin(self.push(config, snapshot))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::defs::ReporterConfig;
+    use crate::metrics::histogram::metric::Histogram;
+    use crate::metrics::sample::Sample;
+    use crate::metrics::tag::Tag;
+    use crate::workers::registry::influx::{histogram_line, sample_line, write_url};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_write_url() {
+        let v1: ReporterConfig =
+            serde_yaml::from_str("influx_write_url: http://influx:8086/write?db=apm").unwrap();
+        let v2: ReporterConfig = serde_yaml::from_str(
+            "influx_write_url: http://influx:8086/api/v2/write?org=acme&bucket=apm&precision=s",
+        )
+        .unwrap();
+
+        assert_eq!(
+            write_url(&v1).as_str(),
+            "http://influx:8086/write?db=apm&precision=ms"
+        );
+        assert_eq!(
+            write_url(&v2).as_str(),
+            "http://influx:8086/api/v2/write?org=acme&bucket=apm&precision=ms"
+        );
+    }
+
+    #[test]
+    fn test_histogram_line() {
+        let mut histogram = Histogram::new(
+            "palantir_apm".to_string(),
+            vec![
+                Tag::new("palantir_span", "db query"),
+                Tag::new("palantir_application_hash", ""),
+                Tag::new("pod", "web-1"),
+            ],
+        );
+        histogram.track(1);
+        histogram.track(300);
+        let mut extra_labels = BTreeMap::new();
+        extra_labels.insert("pod".to_string(), "web=2".to_string());
+
+        let line = histogram_line(&histogram, &extra_labels);
+
+        assert!(line.starts_with(
+            "palantir_apm,palantir_span=db\\ query,pod=web\\=2 le_255=1i,le_511=2i,le_1023=2i,"
+        ));
+        assert!(line.ends_with(",le_68719476735=2i,le_inf=2i,count=2i,sum=301i"));
+    }
+
+    #[test]
+    fn test_sample_line() {
+        let sample = Sample::new(
+            "palantir_agent_push_responses_total",
+            vec![Tag::new("class", "success")],
+            3,
+        );
+
+        assert_eq!(
+            sample_line(&sample, &BTreeMap::new()),
+            "palantir_agent_push_responses_total,class=success value=3i"
+        );
+    }
+}
@@ -1,5 +1,22 @@
 pub mod apm;
+mod auth;
+mod connector;
+mod delivery;
+mod encoder;
 mod error;
+pub mod exporter;
+mod file;
+mod graphite;
 pub mod hc;
+mod influx;
+mod otlp;
+mod payload;
 mod processor;
+mod push;
+mod remote_write;
 mod reporter;
+pub mod scrape;
+mod self_metrics;
+mod spool;
+mod vm;
+mod writer;
@@ -0,0 +1,614 @@
+use crate::config::defs::{Compression, ExporterKind, OtlpHistogram, ReporterConfig};
+use crate::metrics::histogram::metric::Histogram;
+use crate::metrics::sample::Sample;
+use crate::metrics::tag::Tag;
+use crate::workers::registry::delivery::{Delivery, Report};
+use crate::workers::registry::encoder::Encoder;
+use crate::workers::registry::exporter::{ExportFuture, Exporter, Snapshot};
+use hyper::body::Bytes;
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::HeaderMap;
+use prost::Message;
+use proto::{
+    any_value, metric, number_data_point, AnyValue, Buckets, ExponentialHistogram,
+    ExponentialHistogramDataPoint, ExportMetricsServiceRequest, Gauge, HistogramDataPoint,
+    InstrumentationScope, KeyValue, Metric, NumberDataPoint, Resource, ResourceMetrics,
+    ScopeMetrics, Sum,
+};
+use std::collections::BTreeMap;
+use std::io::Result as IOResult;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SERVICE_NAME_ATTRIBUTE: &str = "service.name";
+const SERVICE_NAME: &str = "palantir-agent";
+/// agent tracks durations in microseconds, UCUM unit
+const HISTOGRAM_UNIT: &str = "us";
+/// counters are told from gauges by Prometheus naming convention
+const COUNTER_SUFFIX: &str = "_total";
+const CUMULATIVE: i32 = 2;
+
+/// messages of OTLP metrics (opentelemetry.proto.metrics.v1), only fields the agent sets
+mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ExportMetricsServiceRequest {
+        #[prost(message, repeated, tag = "1")]
+        pub resource_metrics: Vec<ResourceMetrics>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ResourceMetrics {
+        #[prost(message, optional, tag = "1")]
+        pub resource: Option<Resource>,
+        #[prost(message, repeated, tag = "2")]
+        pub scope_metrics: Vec<ScopeMetrics>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Resource {
+        #[prost(message, repeated, tag = "1")]
+        pub attributes: Vec<KeyValue>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ScopeMetrics {
+        #[prost(message, optional, tag = "1")]
+        pub scope: Option<InstrumentationScope>,
+        #[prost(message, repeated, tag = "2")]
+        pub metrics: Vec<Metric>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct InstrumentationScope {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(string, tag = "2")]
+        pub version: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct KeyValue {
+        #[prost(string, tag = "1")]
+        pub key: String,
+        #[prost(message, optional, tag = "2")]
+        pub value: Option<AnyValue>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct AnyValue {
+        #[prost(oneof = "any_value::Value", tags = "1")]
+        pub value: Option<any_value::Value>,
+    }
+
+    pub mod any_value {
+        #[derive(Clone, PartialEq, prost::Oneof)]
+        pub enum Value {
+            #[prost(string, tag = "1")]
+            StringValue(String),
+        }
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Metric {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(string, tag = "3")]
+        pub unit: String,
+        #[prost(oneof = "metric::Data", tags = "5, 7, 9, 10")]
+        pub data: Option<metric::Data>,
+    }
+
+    pub mod metric {
+        #[derive(Clone, PartialEq, prost::Oneof)]
+        pub enum Data {
+            #[prost(message, tag = "5")]
+            Gauge(super::Gauge),
+            #[prost(message, tag = "7")]
+            Sum(super::Sum),
+            #[prost(message, tag = "9")]
+            Histogram(super::Histogram),
+            #[prost(message, tag = "10")]
+            ExponentialHistogram(super::ExponentialHistogram),
+        }
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Gauge {
+        #[prost(message, repeated, tag = "1")]
+        pub data_points: Vec<NumberDataPoint>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Sum {
+        #[prost(message, repeated, tag = "1")]
+        pub data_points: Vec<NumberDataPoint>,
+        #[prost(int32, tag = "2")]
+        pub aggregation_temporality: i32,
+        #[prost(bool, tag = "3")]
+        pub is_monotonic: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Histogram {
+        #[prost(message, repeated, tag = "1")]
+        pub data_points: Vec<HistogramDataPoint>,
+        #[prost(int32, tag = "2")]
+        pub aggregation_temporality: i32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ExponentialHistogram {
+        #[prost(message, repeated, tag = "1")]
+        pub data_points: Vec<ExponentialHistogramDataPoint>,
+        #[prost(int32, tag = "2")]
+        pub aggregation_temporality: i32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct NumberDataPoint {
+        #[prost(message, repeated, tag = "7")]
+        pub attributes: Vec<KeyValue>,
+        #[prost(fixed64, tag = "2")]
+        pub start_time_unix_nano: u64,
+        #[prost(fixed64, tag = "3")]
+        pub time_unix_nano: u64,
+        #[prost(oneof = "number_data_point::Value", tags = "6")]
+        pub value: Option<number_data_point::Value>,
+    }
+
+    pub mod number_data_point {
+        #[derive(Clone, PartialEq, prost::Oneof)]
+        pub enum Value {
+            #[prost(sfixed64, tag = "6")]
+            AsInt(i64),
+        }
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct HistogramDataPoint {
+        #[prost(message, repeated, tag = "9")]
+        pub attributes: Vec<KeyValue>,
+        #[prost(fixed64, tag = "2")]
+        pub start_time_unix_nano: u64,
+        #[prost(fixed64, tag = "3")]
+        pub time_unix_nano: u64,
+        #[prost(fixed64, tag = "4")]
+        pub count: u64,
+        #[prost(double, optional, tag = "5")]
+        pub sum: Option<f64>,
+        #[prost(fixed64, repeated, tag = "6")]
+        pub bucket_counts: Vec<u64>,
+        #[prost(double, repeated, tag = "7")]
+        pub explicit_bounds: Vec<f64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ExponentialHistogramDataPoint {
+        #[prost(message, repeated, tag = "1")]
+        pub attributes: Vec<KeyValue>,
+        #[prost(fixed64, tag = "2")]
+        pub start_time_unix_nano: u64,
+        #[prost(fixed64, tag = "3")]
+        pub time_unix_nano: u64,
+        #[prost(fixed64, tag = "4")]
+        pub count: u64,
+        #[prost(double, optional, tag = "5")]
+        pub sum: Option<f64>,
+        #[prost(sint32, tag = "6")]
+        pub scale: i32,
+        #[prost(fixed64, tag = "7")]
+        pub zero_count: u64,
+        #[prost(message, optional, tag = "8")]
+        pub positive: Option<Buckets>,
+        #[prost(double, tag = "14")]
+        pub zero_threshold: f64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Buckets {
+        #[prost(sint32, tag = "1")]
+        pub offset: i32,
+        #[prost(uint64, repeated, tag = "2")]
+        pub bucket_counts: Vec<u64>,
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+fn string_attribute(key: &str, value: &str) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue {
+            value: Some(any_value::Value::StringValue(value.to_string())),
+        }),
+    }
+}
+
+fn attributes(tags: &[Tag]) -> Vec<KeyValue> {
+    tags.iter()
+        .map(|tag| string_attribute(&tag.key, &tag.value))
+        .collect()
+}
+
+/// extra labels describe the agent, so they go to resource rather than to every point
+fn resource(extra_labels: &BTreeMap<String, String>) -> Resource {
+    let mut merged = BTreeMap::new();
+    merged.insert(SERVICE_NAME_ATTRIBUTE, SERVICE_NAME);
+    merged.extend(
+        extra_labels
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str())),
+    );
+    Resource {
+        attributes: merged
+            .into_iter()
+            .map(|(key, value)| string_attribute(key, value))
+            .collect(),
+    }
+}
+
+/// bucket `i` of scale 0 is (2^i, 2^(i+1)]
+fn exponential_index(upper: u64) -> i32 {
+    (upper + 1).trailing_zeros() as i32 - 1
+}
+
+/// agent buckets [2^i, 2^(i+1) - 1] are scale 0 buckets (2^i, 2^(i+1)] off by one microsecond
+/// first bucket [0, 255] can't be split, so it's reported as zero bucket with threshold 255
+/// the open last bucket is reported as the next power of two
+fn exponential_point(histogram: &Histogram, time_unix_nano: u64) -> ExponentialHistogramDataPoint {
+    let buckets: Vec<(u64, u64)> = histogram.bucket_counts().collect();
+    let (zero_threshold, zero_count) = buckets[0];
+    let counts: Vec<u64> = buckets[1..].iter().map(|(_, count)| *count).collect();
+    let positive = match counts.iter().position(|count| *count > 0) {
+        Some(first) => {
+            // there is a non-zero count, so there is the last one
+            let last = counts.iter().rposition(|count| *count > 0).unwrap();
+            Buckets {
+                offset: exponential_index(buckets[1].0) + first as i32,
+                bucket_counts: counts[first..=last].to_vec(),
+            }
+        }
+        None => Buckets::default(),
+    };
+
+    ExponentialHistogramDataPoint {
+        attributes: attributes(histogram.tags()),
+        start_time_unix_nano: unix_nanos(histogram.started()),
+        time_unix_nano,
+        count: histogram.count(),
+        sum: Some(histogram.sum() as f64),
+        scale: 0,
+        zero_count,
+        positive: Some(positive),
+        zero_threshold: zero_threshold as f64,
+    }
+}
+
+/// bounds are upper-inclusive in OTLP as in the agent, so buckets are exact
+fn explicit_point(histogram: &Histogram, time_unix_nano: u64) -> HistogramDataPoint {
+    let (bounds, counts): (Vec<u64>, Vec<u64>) = histogram.bucket_counts().unzip();
+    HistogramDataPoint {
+        attributes: attributes(histogram.tags()),
+        start_time_unix_nano: unix_nanos(histogram.started()),
+        time_unix_nano,
+        count: histogram.count(),
+        sum: Some(histogram.sum() as f64),
+        bucket_counts: counts,
+        // last bucket is open, it has no bound
+        explicit_bounds: bounds[..bounds.len() - 1]
+            .iter()
+            .map(|bound| *bound as f64)
+            .collect(),
+    }
+}
+
+fn histogram_metric(histogram: &Histogram, kind: OtlpHistogram, time_unix_nano: u64) -> Metric {
+    let data = match kind {
+        OtlpHistogram::Exponential => metric::Data::ExponentialHistogram(ExponentialHistogram {
+            data_points: vec![exponential_point(histogram, time_unix_nano)],
+            aggregation_temporality: CUMULATIVE,
+        }),
+        OtlpHistogram::Explicit => metric::Data::Histogram(proto::Histogram {
+            data_points: vec![explicit_point(histogram, time_unix_nano)],
+            aggregation_temporality: CUMULATIVE,
+        }),
+    };
+    Metric {
+        name: histogram.name().to_string(),
+        unit: HISTOGRAM_UNIT.to_string(),
+        data: Some(data),
+    }
+}
+
+/// agent's own counters become monotonic sums counted since `started`, the rest are gauges
+fn sample_metric(sample: Sample, started_unix_nano: u64, time_unix_nano: u64) -> Metric {
+    let point = NumberDataPoint {
+        attributes: attributes(&sample.tags),
+        start_time_unix_nano: started_unix_nano,
+        time_unix_nano,
+        value: Some(number_data_point::Value::AsInt(
+            sample.value.min(i64::MAX as u64) as i64,
+        )),
+    };
+    let data = if sample.name.ends_with(COUNTER_SUFFIX) {
+        metric::Data::Sum(Sum {
+            data_points: vec![point],
+            aggregation_temporality: CUMULATIVE,
+            is_monotonic: true,
+        })
+    } else {
+        metric::Data::Gauge(Gauge {
+            data_points: vec![point],
+        })
+    };
+    Metric {
+        name: sample.name,
+        unit: String::new(),
+        data: Some(data),
+    }
+}
+
+/// splits metrics into requests of at most `max_payload_bytes` (before compression)
+/// every request carries the same resource and scope
+fn encode_report(
+    resource: Resource,
+    metrics: Vec<Metric>,
+    max_payload_bytes: usize,
+    compression: Compression,
+) -> IOResult<Vec<Bytes>> {
+    let request = |metrics: Vec<Metric>| ExportMetricsServiceRequest {
+        resource_metrics: vec![ResourceMetrics {
+            resource: Some(resource.clone()),
+            scope_metrics: vec![ScopeMetrics {
+                scope: Some(InstrumentationScope {
+                    name: SERVICE_NAME.to_string(),
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                }),
+                metrics,
+            }],
+        }],
+    };
+
+    let mut requests = Vec::new();
+    let mut current = Vec::new();
+    let mut size = 0;
+    for metric in metrics {
+        let metric_size = prost::encoding::message::encoded_len(2, &metric);
+        if size > 0 && size + metric_size > max_payload_bytes {
+            requests.push(request(std::mem::take(&mut current)));
+            size = 0;
+        }
+        current.push(metric);
+        size += metric_size;
+    }
+    if size > 0 {
+        requests.push(request(current));
+    }
+
+    requests
+        .iter()
+        .map(|request| {
+            let mut encoded = Vec::with_capacity(request.encoded_len());
+            // can't fail, buffer grows as needed
+            request.encode(&mut encoded).unwrap();
+            let mut encoder = Encoder::new(compression)?;
+            encoder.write(&encoded)?;
+            Ok(Bytes::from(encoder.finish()?))
+        })
+        .collect()
+}
+
+fn otlp_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("application/x-protobuf"),
+    );
+    headers
+}
+
+/// `started` is start of agent's own counters
+fn metrics(
+    config: &ReporterConfig,
+    started: SystemTime,
+    own: Vec<Sample>,
+    snapshot: &Snapshot,
+) -> Vec<Metric> {
+    let time_unix_nano = unix_nanos(snapshot.timestamp);
+    let started_unix_nano = unix_nanos(started);
+    let mut metrics: Vec<Metric> = own
+        .into_iter()
+        .map(|sample| sample_metric(sample, started_unix_nano, time_unix_nano))
+        .collect();
+    metrics.push(histogram_metric(
+        &snapshot.handle_time,
+        config.otlp_histogram,
+        time_unix_nano,
+    ));
+    for hc in snapshot.collections.iter() {
+        for histogram in hc.histograms() {
+            metrics.push(histogram_metric(
+                histogram,
+                config.otlp_histogram,
+                time_unix_nano,
+            ));
+        }
+    }
+    metrics
+}
+
+/// pushes OTLP/HTTP protobuf requests to OpenTelemetry Collector or any OTLP receiver
+/// histograms are cumulative, a metric per data point, collector groups them as needed
+pub struct OtlpExporter {
+    delivery: Delivery,
+    /// start of agent's own counters, they live as long as exporter does
+    started: SystemTime,
+}
+
+impl OtlpExporter {
+    pub fn new(target: String) -> Self {
+        Self {
+            delivery: Delivery::new(target, ExporterKind::Otlp),
+            started: SystemTime::now(),
+        }
+    }
+
+    async fn push(&mut self, config: &ReporterConfig, snapshot: &Snapshot) {
+        let url = config.otlp_url.clone().unwrap_or_default();
+        let started = self.started;
+        let max_payload_bytes = config
+            .max_payload_bytes
+            .map(|limit| limit as usize)
+            .unwrap_or(usize::MAX);
+        self.delivery
+            .push_report(config, url, otlp_headers(), |_, own| async move {
+                encode_report(
+                    resource(&config.extra_labels),
+                    metrics(config, started, own, snapshot),
+                    max_payload_bytes,
+                    config.compression,
+                )
+                .map(Report::Encoded)
+            })
+            .await;
+    }
+}
+
+impl Exporter for OtlpExporter {
+    fn export<'a>(
+        &'a mut self,
+        config: &'a ReporterConfig,
+        snapshot: &'a Snapshot,
+    ) -> ExportFuture<'a> {
+        Box::pin(self.push(config, snapshot))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::defs::Compression;
+    use crate::metrics::histogram::metric::Histogram;
+    use crate::metrics::sample::Sample;
+    use crate::workers::registry::otlp::proto::{metric, Buckets, ExportMetricsServiceRequest};
+    use crate::workers::registry::otlp::{
+        encode_report, explicit_point, exponential_point, resource, sample_metric,
+    };
+    use flate2::read::GzDecoder;
+    use prost::Message;
+    use std::collections::BTreeMap;
+    use std::io::Read;
+
+    fn histogram() -> Histogram {
+        let mut histogram = Histogram::new("palantir_apm".to_string(), Vec::new());
+        histogram.track(100);
+        histogram.track(300);
+        histogram.track(2000);
+        histogram.track(2000);
+        histogram
+    }
+
+    #[test]
+    fn test_exponential_point() {
+        let point = exponential_point(&histogram(), 1000);
+
+        assert_eq!(point.count, 4);
+        assert_eq!(point.sum, Some(4400.0));
+        assert_eq!(point.zero_count, 1);
+        assert_eq!(point.zero_threshold, 255.0);
+        // 300 -> (256, 512], 2000 -> (1024, 2048]
+        assert_eq!(
+            point.positive,
+            Some(Buckets {
+                offset: 8,
+                bucket_counts: vec![1, 0, 2],
+            })
+        );
+    }
+
+    #[test]
+    fn test_explicit_point() {
+        let point = explicit_point(&histogram(), 1000);
+
+        assert_eq!(point.explicit_bounds.len(), 29);
+        assert_eq!(point.explicit_bounds[0], 255.0);
+        assert_eq!(point.explicit_bounds[28], 68719476735.0);
+        assert_eq!(point.bucket_counts.len(), 30);
+        assert_eq!(&point.bucket_counts[..4], &[1, 1, 0, 2]);
+        assert_eq!(point.bucket_counts.iter().sum::<u64>(), point.count);
+    }
+
+    #[test]
+    fn test_sample_metric() {
+        let counter = sample_metric(
+            Sample::new("palantir_agent_push_retries_total", Vec::new(), 3),
+            1,
+            2,
+        );
+        let gauge = sample_metric(
+            Sample::new("palantir_agent_spool_payloads", Vec::new(), 3),
+            1,
+            2,
+        );
+
+        match counter.data {
+            Some(metric::Data::Sum(sum)) => assert!(sum.is_monotonic),
+            data => panic!("wrong data: {:?}", data),
+        }
+        match gauge.data {
+            Some(metric::Data::Gauge(_)) => (),
+            data => panic!("wrong data: {:?}", data),
+        }
+    }
+
+    #[test]
+    fn test_encode_report() {
+        let metrics: Vec<_> = (1..=10)
+            .map(|i| {
+                sample_metric(
+                    Sample::new("palantir_agent_spool_payloads", Vec::new(), i),
+                    1,
+                    2,
+                )
+            })
+            .collect();
+        let metric_size = prost::encoding::message::encoded_len(2, &metrics[0]);
+        let mut extra_labels = BTreeMap::new();
+        extra_labels.insert("dc".to_string(), "eu".to_string());
+
+        let whole = encode_report(
+            resource(&extra_labels),
+            metrics.clone(),
+            usize::MAX,
+            Compression::Gzip,
+        )
+        .unwrap();
+        let parts = encode_report(
+            resource(&extra_labels),
+            metrics.clone(),
+            metric_size * 4,
+            Compression::None,
+        )
+        .unwrap();
+
+        assert_eq!(whole.len(), 1);
+        let mut raw = Vec::new();
+        GzDecoder::new(&whole[0][..]).read_to_end(&mut raw).unwrap();
+        let request = ExportMetricsServiceRequest::decode(&raw[..]).unwrap();
+        assert_eq!(
+            request.resource_metrics[0].scope_metrics[0].metrics,
+            metrics
+        );
+        assert_eq!(parts.len(), 3);
+        let mut decoded = Vec::new();
+        for part in parts {
+            let request = ExportMetricsServiceRequest::decode(&part[..]).unwrap();
+            let resource_metrics = &request.resource_metrics[0];
+            assert_eq!(resource_metrics.resource, Some(resource(&extra_labels)));
+            decoded.extend(resource_metrics.scope_metrics[0].metrics.clone());
+        }
+        assert_eq!(decoded, metrics);
+    }
+}
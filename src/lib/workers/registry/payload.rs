@@ -0,0 +1,115 @@
+use hyper::body::Bytes;
+use hyper::Body;
+use log::error;
+use std::io::Result as IOResult;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+
+/// file payloads are read back in chunks of this size
+const READ_CHUNK_BYTES: usize = 64 * 1024;
+/// part files are told from spooled payloads by it
+pub const PART_FILE_EXTENSION: &str = "tmp";
+
+static NEXT_PART: AtomicU64 = AtomicU64::new(0);
+
+/// encoded payload kept for retries and spool
+pub enum Payload {
+    /// payloads encoded at once, e.g. protobuf messages
+    Memory(Bytes),
+    /// report part written while it was streamed, so it's never kept in memory as a whole
+    File(PartFile),
+}
+
+impl Payload {
+    /// request body, file payloads are streamed
+    pub async fn body(&self) -> IOResult<Body> {
+        let part = match self {
+            Payload::Memory(payload) => return Ok(Body::from(payload.clone())),
+            Payload::File(part) => part,
+        };
+        let mut file = File::open(&part.path).await?;
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; READ_CHUNK_BYTES];
+            loop {
+                match file.read(&mut buf).await {
+                    Ok(0) => break,
+                    Ok(read) => {
+                        if sender
+                            .send_data(Bytes::copy_from_slice(&buf[..read]))
+                            .await
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                    Err(err) => {
+                        error!("Unable to read payload file, {:?}", err);
+                        sender.abort();
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(body)
+    }
+}
+
+/// file is removed when dropped, unless it's been moved to spool
+pub struct PartFile {
+    path: PathBuf,
+}
+
+impl PartFile {
+    /// new empty file in `dir`, named uniquely within the process
+    pub async fn create(dir: &Path) -> IOResult<(Self, File)> {
+        let path = dir.join(format!(
+            "part-{}-{}.{}",
+            std::process::id(),
+            NEXT_PART.fetch_add(1, Ordering::Relaxed),
+            PART_FILE_EXTENSION
+        ));
+        let file = File::create(&path).await?;
+        Ok((Self { path }, file))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PartFile {
+    fn drop(&mut self) {
+        // it's not there once moved to spool
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::workers::registry::payload::{PartFile, Payload};
+    use hyper::body::to_bytes;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_file_body() {
+        let dir = std::env::temp_dir();
+        let (part, mut file) = PartFile::create(&dir).await.unwrap();
+        let content = "palantir_apm_count 1 1000\n".repeat(10_000);
+        file.write_all(content.as_bytes()).await.unwrap();
+        file.flush().await.unwrap();
+        let path = part.path().to_path_buf();
+        let payload = Payload::File(part);
+
+        // every retry reads the file from the beginning
+        for _ in 0..2 {
+            let body = to_bytes(payload.body().await.unwrap()).await.unwrap();
+            assert_eq!(body, content.as_bytes());
+        }
+
+        drop(payload);
+        assert!(!path.exists());
+    }
+}
@@ -13,7 +13,7 @@ use std::time::Instant;
 
 pub struct Processor {
     rx: Receiver<ProtoMessage>,
-    client_metrics: Arc<Mutex<HashMap<u64, HistogramCollection>>>,
+    client_metrics: Arc<Mutex<HashMap<u64, Arc<HistogramCollection>>>>,
     handle_time: Arc<Mutex<Histogram>>,
 
     keepalive_reporter: Receiver<()>,
@@ -22,7 +22,7 @@ pub struct Processor {
 impl Processor {
     pub fn new(
         rx: Receiver<ProtoMessage>,
-        client_metrics: Arc<Mutex<HashMap<u64, HistogramCollection>>>,
+        client_metrics: Arc<Mutex<HashMap<u64, Arc<HistogramCollection>>>>,
         handle_time: Arc<Mutex<Histogram>>,
         keepalive_reporter: Receiver<()>,
     ) -> Self {
@@ -55,8 +55,9 @@ impl Processor {
         let mut locked = self.client_metrics.lock().unwrap();
         let hc = locked
             .entry(checksum)
-            .or_insert(HistogramCollection::from(&msg));
-        hc.process(msg);
+            .or_insert_with(|| Arc::new(HistogramCollection::from(&msg)));
+        // collection is copied only if a report still holds it
+        Arc::make_mut(hc).process(msg);
 
         let elapsed = now.elapsed();
         trace!("processing took {} us", elapsed.as_micros());
@@ -0,0 +1,288 @@
+use crate::config::defs::{Compression, RetryConfig};
+use crate::workers::registry::connector::Connector;
+use crate::workers::registry::payload::Payload;
+use crate::workers::registry::self_metrics::SelfMetrics;
+use hyper::body::HttpBody;
+use hyper::header::CONTENT_ENCODING;
+use hyper::{Body, Client, HeaderMap, Request, StatusCode};
+use log::{error, info, warn};
+use std::fmt;
+use std::time::{Duration, Instant};
+use tokio::task::JoinError;
+
+/// kept in logs, the rest of response is discarded
+const MAX_LOGGED_BODY_BYTES: usize = 512;
+
+#[derive(Debug)]
+pub enum PushError {
+    Build(hyper::http::Error),
+    Request(hyper::Error),
+    Timeout(Duration),
+    /// status with (truncated) response body
+    Status(StatusCode, String),
+    /// push task panicked or was cancelled
+    Aborted(JoinError),
+    /// payload file couldn't be read
+    Payload(std::io::Error),
+}
+
+impl PushError {
+    /// network errors, timeouts, 5xx and throttling may go away on their own
+    /// other 4xx mean target will reject the same payload again
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Build(_) => false,
+            Self::Request(_) | Self::Timeout(_) | Self::Aborted(_) => true,
+            // it's retried, then spooled (or dropped) as payloads that target never got
+            Self::Payload(_) => true,
+            Self::Status(status, _) => is_retryable_status(*status),
+        }
+    }
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+}
+
+/// reads at most `limit` bytes of body, the rest is discarded
+async fn read_body_prefix(mut body: Body, limit: usize) -> String {
+    let mut prefix = Vec::new();
+    let mut truncated = false;
+    while let Some(Ok(chunk)) = body.data().await {
+        let left = limit - prefix.len();
+        if chunk.len() > left {
+            prefix.extend_from_slice(&chunk[..left]);
+            truncated = true;
+            break;
+        }
+        prefix.extend_from_slice(&chunk);
+    }
+
+    let mut text = String::from_utf8_lossy(&prefix).trim().to_string();
+    if truncated {
+        text.push_str("...");
+    }
+    text
+}
+
+impl fmt::Display for PushError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Build(err) => write!(f, "unable to build request, {}", err),
+            Self::Request(err) => write!(f, "request failed, {}", err),
+            Self::Timeout(timeout) => write!(f, "timed out after {}ms", timeout.as_millis()),
+            Self::Status(status, body) => write!(f, "got {} response: {}", status, body),
+            Self::Aborted(err) => write!(f, "push aborted, {}", err),
+            Self::Payload(err) => write!(f, "unable to read payload, {}", err),
+        }
+    }
+}
+
+/// delay before retry number `attempt` (1 for the first retry)
+pub fn backoff(retry: &RetryConfig, attempt: u32) -> Duration {
+    let multiplier = 1u64
+        .checked_shl(attempt.saturating_sub(1))
+        .unwrap_or(u64::MAX);
+    let delay = retry.initial_backoff_ms.saturating_mul(multiplier);
+    Duration::from_millis(delay.min(retry.max_backoff_ms))
+}
+
+/// delay before retry number `attempt`, if attempts are left and it starts within `max_retry_time`
+/// of the first attempt, so that retries never hold up the next report for long
+pub fn next_retry(
+    retry: &RetryConfig,
+    attempt: u32,
+    elapsed: Duration,
+    max_retry_time: Duration,
+) -> Option<Duration> {
+    let delay = backoff(retry, attempt);
+    if attempt < retry.max_attempts && elapsed + delay <= max_retry_time {
+        Some(delay)
+    } else {
+        None
+    }
+}
+
+pub struct Pusher {
+    /// target name, used in logs
+    pub target: String,
+    pub client: Client<Connector>,
+    pub url: String,
+    pub timeout: Duration,
+    pub retry: RetryConfig,
+    /// report period, retries of a report stop after it, see `next_retry`
+    pub max_retry_time: Duration,
+    pub headers: HeaderMap,
+}
+
+impl Pusher {
+    pub async fn send(&self, body: Body, compression: Compression) -> Result<(), PushError> {
+        let mut request = Request::post(self.url.as_str());
+        if let Some(headers) = request.headers_mut() {
+            headers.extend(self.headers.clone());
+        }
+        if let Some(encoding) = compression.content_encoding() {
+            request = request.header(CONTENT_ENCODING, encoding);
+        }
+        let request = request.body(body).map_err(PushError::Build)?;
+        let exchange = async {
+            let response = self.client.request(request).await?;
+            let status = response.status();
+            let body = read_body_prefix(response.into_body(), MAX_LOGGED_BODY_BYTES).await;
+            Ok((status, body))
+        };
+        let (status, body) = tokio::time::timeout(self.timeout, exchange)
+            .await
+            .map_err(|_| PushError::Timeout(self.timeout))?
+            .map_err(PushError::Request)?;
+
+        if !status.is_success() {
+            return Err(PushError::Status(status, body));
+        }
+        info!("got {} from {}", status.as_u16(), self.target);
+        Ok(())
+    }
+
+    pub async fn send_payload(
+        &self,
+        payload: &Payload,
+        compression: Compression,
+    ) -> Result<(), PushError> {
+        let body = payload.body().await.map_err(PushError::Payload)?;
+        self.send(body, compression).await
+    }
+
+    /// Err -> last error after all attempts failed
+    pub async fn push_with_retry(
+        &self,
+        payload: &Payload,
+        compression: Compression,
+        started: Instant,
+        self_metrics: &mut SelfMetrics,
+    ) -> Result<(), PushError> {
+        let first = self.send_payload(payload, compression).await;
+        self.retry(first, payload, compression, started, self_metrics)
+            .await
+    }
+
+    /// retries after `first` attempt until push succeeds, fails permanently or attempts run out
+    /// `started` is when the report push began, so all of its payloads share one retry deadline
+    pub async fn retry(
+        &self,
+        first: Result<(), PushError>,
+        payload: &Payload,
+        compression: Compression,
+        started: Instant,
+        self_metrics: &mut SelfMetrics,
+    ) -> Result<(), PushError> {
+        let mut attempt = 1;
+        let mut result = first;
+        loop {
+            match &result {
+                Ok(()) => self_metrics.push_success.inc(),
+                Err(err) if err.is_retryable() => self_metrics.push_retryable.inc(),
+                Err(_) => self_metrics.push_permanent.inc(),
+            }
+            let delay = match &result {
+                Err(err) if err.is_retryable() => {
+                    next_retry(&self.retry, attempt, started.elapsed(), self.max_retry_time)
+                }
+                _ => None,
+            };
+            match (result, delay) {
+                (Ok(()), _) => return Ok(()),
+                (Err(err), Some(delay)) => {
+                    warn!(
+                        "Push attempt {} to {} failed, retrying in {}ms, {}",
+                        attempt,
+                        self.target,
+                        delay.as_millis(),
+                        err
+                    );
+                    self_metrics.push_retries.inc();
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                    result = self.send_payload(payload, compression).await;
+                }
+                (Err(err), None) => {
+                    error!(
+                        "Push to {} failed after {} attempts, {}",
+                        self.target, attempt, err
+                    );
+                    self_metrics.push_failures.inc();
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::defs::RetryConfig;
+    use crate::workers::registry::push::{
+        backoff, is_retryable_status, next_retry, read_body_prefix,
+    };
+    use hyper::{Body, StatusCode};
+    use std::time::Duration;
+
+    #[test]
+    fn test_backoff() {
+        let retry = RetryConfig {
+            max_attempts: 10,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 5000,
+        };
+
+        let delays: Vec<u128> = (1..=6)
+            .map(|attempt| backoff(&retry, attempt).as_millis())
+            .collect();
+
+        assert_eq!(delays, vec![500, 1000, 2000, 4000, 5000, 5000]);
+        assert_eq!(backoff(&retry, 100).as_millis(), 5000);
+    }
+
+    #[test]
+    fn test_next_retry() {
+        let retry = RetryConfig {
+            max_attempts: 3,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 5000,
+        };
+        let period = Duration::from_secs(10);
+
+        assert_eq!(
+            next_retry(&retry, 1, Duration::ZERO, period),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(next_retry(&retry, 3, Duration::ZERO, period), None);
+        assert_eq!(
+            next_retry(&retry, 2, Duration::from_millis(9_500), period),
+            None
+        );
+    }
+
+    #[test]
+    fn test_retryable_status() {
+        let retryable = vec![500, 502, 503, 504, 429, 408];
+        let permanent = vec![400, 401, 403, 404, 413];
+
+        for status in retryable {
+            assert!(is_retryable_status(StatusCode::from_u16(status).unwrap()));
+        }
+        for status in permanent {
+            assert!(!is_retryable_status(StatusCode::from_u16(status).unwrap()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_body_prefix() {
+        let short = read_body_prefix(Body::from("cannot parse line\n"), 32).await;
+        let long = read_body_prefix(Body::from("x".repeat(100)), 8).await;
+
+        assert_eq!(short, "cannot parse line");
+        assert_eq!(long, "xxxxxxxx...");
+    }
+}
@@ -0,0 +1,252 @@
+use crate::config::defs::{ExporterKind, ReporterConfig};
+use crate::metrics::sample::Sample;
+use crate::metrics::traits::PrometheusMetric;
+use crate::workers::registry::delivery::{Delivery, Report};
+use crate::workers::registry::exporter::{ExportFuture, Exporter, Snapshot};
+use hyper::body::Bytes;
+use hyper::header::{HeaderName, HeaderValue, CONTENT_ENCODING, CONTENT_TYPE, USER_AGENT};
+use hyper::HeaderMap;
+use prost::Message;
+use std::collections::BTreeMap;
+
+const REMOTE_WRITE_VERSION_HEADER: &str = "x-prometheus-remote-write-version";
+const REMOTE_WRITE_VERSION: &str = "0.1.0";
+/// spec requires senders to identify themselves
+const AGENT: &str = concat!("palantir-agent/", env!("CARGO_PKG_VERSION"));
+const METRIC_NAME_LABEL: &str = "__name__";
+
+/// messages of remote_write protocol (prompb), only fields the agent sets
+#[derive(Clone, PartialEq, Message)]
+struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TimeSeries {
+    /// sorted by name, as receivers expect
+    #[prost(message, repeated, tag = "1")]
+    labels: Vec<Label>,
+    #[prost(message, repeated, tag = "2")]
+    samples: Vec<ProtoSample>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Label {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    value: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoSample {
+    #[prost(double, tag = "1")]
+    value: f64,
+    #[prost(int64, tag = "2")]
+    timestamp: i64,
+}
+
+/// extra labels win over tags with the same name, receivers reject duplicated labels
+fn time_series(
+    sample: Sample,
+    extra_labels: &BTreeMap<String, String>,
+    timestamp_ms: i64,
+) -> TimeSeries {
+    let mut labels: BTreeMap<String, String> = sample
+        .tags
+        .into_iter()
+        .map(|tag| (tag.key, tag.value))
+        .collect();
+    labels.extend(extra_labels.clone());
+    labels.insert(METRIC_NAME_LABEL.to_string(), sample.name);
+
+    TimeSeries {
+        labels: labels
+            .into_iter()
+            .map(|(name, value)| Label { name, value })
+            .collect(),
+        samples: vec![ProtoSample {
+            value: sample.value as f64,
+            timestamp: timestamp_ms,
+        }],
+    }
+}
+
+/// splits series into write requests of at most `max_payload_bytes` (before compression)
+/// every request is snappy-compressed as a single block, as protocol requires
+fn encode_report(
+    series: Vec<TimeSeries>,
+    max_payload_bytes: usize,
+) -> Result<Vec<Bytes>, snap::Error> {
+    let mut requests = Vec::new();
+    let mut current = WriteRequest::default();
+    let mut size = 0;
+    for ts in series {
+        let ts_size = prost::encoding::message::encoded_len(1, &ts);
+        if size > 0 && size + ts_size > max_payload_bytes {
+            requests.push(std::mem::take(&mut current));
+            size = 0;
+        }
+        current.timeseries.push(ts);
+        size += ts_size;
+    }
+    if size > 0 {
+        requests.push(current);
+    }
+
+    let mut encoder = snap::raw::Encoder::new();
+    requests
+        .iter()
+        .map(|request| {
+            let mut encoded = Vec::with_capacity(request.encoded_len());
+            // can't fail, buffer grows as needed
+            request.encode(&mut encoded).unwrap();
+            Ok(Bytes::from(encoder.compress_vec(&encoded)?))
+        })
+        .collect()
+}
+
+/// headers protocol requires, on top of configured ones
+fn remote_write_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_ENCODING, HeaderValue::from_static("snappy"));
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("application/x-protobuf"),
+    );
+    headers.insert(
+        HeaderName::from_static(REMOTE_WRITE_VERSION_HEADER),
+        HeaderValue::from_static(REMOTE_WRITE_VERSION),
+    );
+    headers.insert(USER_AGENT, HeaderValue::from_static(AGENT));
+    headers
+}
+
+fn series(config: &ReporterConfig, own: Vec<Sample>, snapshot: &Snapshot) -> Vec<TimeSeries> {
+    let timestamp_ms = snapshot.timestamp_ms() as i64;
+    let mut samples = own;
+    samples.extend(snapshot.handle_time.samples());
+    for hc in snapshot.collections.iter() {
+        samples.extend(hc.samples());
+    }
+    samples
+        .into_iter()
+        .map(|sample| time_series(sample, &config.extra_labels, timestamp_ms))
+        .collect()
+}
+
+/// pushes Prometheus remote_write requests, to Mimir, Cortex, Thanos receive and alike
+/// histograms are sent as classic `le` histograms, extra labels are added to every series
+pub struct RemoteWriteExporter {
+    delivery: Delivery,
+}
+
+impl RemoteWriteExporter {
+    pub fn new(target: String) -> Self {
+        Self {
+            delivery: Delivery::new(target, ExporterKind::RemoteWrite),
+        }
+    }
+
+    async fn push(&mut self, config: &ReporterConfig, snapshot: &Snapshot) {
+        let url = config.remote_write_url.clone().unwrap_or_default();
+        let max_payload_bytes = config
+            .max_payload_bytes
+            .map(|limit| limit as usize)
+            .unwrap_or(usize::MAX);
+        // snappy is part of payload, `Content-Encoding` is among protocol headers
+        self.delivery
+            .push_report(config, url, remote_write_headers(), |_, own| async move {
+                encode_report(series(config, own, snapshot), max_payload_bytes).map(Report::Encoded)
+            })
+            .await;
+    }
+}
+
+impl Exporter for RemoteWriteExporter {
+    fn export<'a>(
+        &'a mut self,
+        config: &'a ReporterConfig,
+        snapshot: &'a Snapshot,
+    ) -> ExportFuture<'a> {
+        Box::pin(self.push(config, snapshot))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::sample::Sample;
+    use crate::metrics::tag::Tag;
+    use crate::workers::registry::remote_write::{
+        encode_report, remote_write_headers, time_series, Label, WriteRequest,
+    };
+    use prost::Message;
+    use std::collections::BTreeMap;
+
+    fn sample(name: &str, value: u64) -> Sample {
+        let tags = vec![Tag::new("pod", "web-1"), Tag::new("le", "255")];
+        Sample::new(name, tags, value)
+    }
+
+    fn label(name: &str, value: &str) -> Label {
+        Label {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_time_series() {
+        let mut extra_labels = BTreeMap::new();
+        extra_labels.insert("pod".to_string(), "web-2".to_string());
+        extra_labels.insert("dc".to_string(), "eu".to_string());
+
+        let ts = time_series(sample("hist_bucket", 3), &extra_labels, 1000);
+
+        assert_eq!(
+            ts.labels,
+            vec![
+                label("__name__", "hist_bucket"),
+                label("dc", "eu"),
+                label("le", "255"),
+                label("pod", "web-2"),
+            ]
+        );
+        assert_eq!(ts.samples.len(), 1);
+        assert_eq!(ts.samples[0].value, 3.0);
+        assert_eq!(ts.samples[0].timestamp, 1000);
+    }
+
+    #[test]
+    fn test_encode_report() {
+        // zero value isn't encoded at all, so every series is of the same size
+        let series: Vec<_> = (1..=10)
+            .map(|i| time_series(sample("hist_count", i), &BTreeMap::new(), 1000))
+            .collect();
+        let ts_size = prost::encoding::message::encoded_len(1, &series[0]);
+
+        let whole = encode_report(series.clone(), usize::MAX).unwrap();
+        let parts = encode_report(series.clone(), ts_size * 4).unwrap();
+
+        assert_eq!(whole.len(), 1);
+        assert_eq!(parts.len(), 3);
+        let mut decoded = Vec::new();
+        for part in parts {
+            let raw = snap::raw::Decoder::new().decompress_vec(&part).unwrap();
+            decoded.extend(WriteRequest::decode(&raw[..]).unwrap().timeseries);
+        }
+        assert_eq!(decoded, series);
+    }
+
+    #[test]
+    fn test_remote_write_headers() {
+        let headers = remote_write_headers();
+        assert_eq!(headers["content-encoding"], "snappy");
+        assert_eq!(headers["x-prometheus-remote-write-version"], "0.1.0");
+        assert_eq!(
+            headers["user-agent"],
+            format!("palantir-agent/{}", env!("CARGO_PKG_VERSION"))
+        );
+    }
+}
@@ -1,71 +1,105 @@
-use crate::constants as c;
+use crate::config::defs::{ReporterConfig, TargetFilter};
 use crate::metrics::histogram::metric::Histogram;
-use crate::metrics::traits::PrometheusMetric;
 use crate::workers::registry::error::RegistryError;
+use crate::workers::registry::exporter::{Exporter, Snapshot};
 use crate::workers::registry::hc::HistogramCollection;
-use hyper::{Body, Client, Request};
-use lazy_static::lazy_static;
-use log::{error, info, trace, warn};
+use log::{error, info, trace};
+use rand::Rng;
 use std::collections::HashMap;
 use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::{Duration, Instant};
-use url::Url;
-
-const REPORT_PERIOD_SECONDS: u64 = 10;
-
-lazy_static! {
-    static ref ADDITIONAL_LABELS: Vec<(String, String)> = {
-        let mut labels = Vec::new();
-        for (key, value) in std::env::vars() {
-            if !key.starts_with(c::EXTRA_LABEL_PREFIX) {
-                trace!("key {} omitted", key);
-                continue;
-            }
-            if !c::EXTRA_LABEL_REGEX.is_match(&key) || !c::EXTRA_LABEL_REGEX.is_match(&value) {
-                warn!("pair {}:{} is invalid", key, value);
-                continue;
-            }
-            let label_name = key.replace(c::EXTRA_LABEL_PREFIX, "").to_lowercase();
-            trace!("adding extra label {}:{}", label_name, value);
-            labels.push((label_name, value));
-        }
-        labels
-    };
-}
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-// TODO make configurable
 // TODO add metrics about report generation time
-// TODO add metrics about victoriametrics response time
 // TODO add reading shared labels from
-pub struct Reporter<'a> {
-    client_metrics: Arc<Mutex<HashMap<u64, HistogramCollection>>>,
+pub struct Reporter {
+    /// target name, used in logs
+    target: String,
+    client_metrics: Arc<Mutex<HashMap<u64, Arc<HistogramCollection>>>>,
     handle_time: Arc<Mutex<Histogram>>,
 
     keepalive_tx: Sender<()>,
 
-    vm_import_url: &'a str,
+    config: Arc<Mutex<ReporterConfig>>,
+
+    /// non-aligned schedule counts periods from here
+    started: SystemTime,
+    /// (jitter_ms it was picked for, offset)
+    jitter: (u64, Duration),
+
+    exporter: Box<dyn Exporter>,
 }
 
-impl Reporter<'_> {
+/// time left until the next report
+/// reports happen every `period` counting from `base`, shifted by `offset` (< period)
+fn next_report_delay(
+    now: SystemTime,
+    base: SystemTime,
+    period: Duration,
+    offset: Duration,
+) -> Duration {
+    let period_ms = period.as_millis().max(1);
+    let elapsed_ms = now.duration_since(base).unwrap_or_default().as_millis();
+    let phase = (elapsed_ms + period_ms - offset.as_millis() % period_ms) % period_ms;
+    Duration::from_millis((period_ms - phase) as u64)
+}
+
+impl Reporter {
     pub fn new(
-        client_metrics: Arc<Mutex<HashMap<u64, HistogramCollection>>>,
+        target: String,
+        client_metrics: Arc<Mutex<HashMap<u64, Arc<HistogramCollection>>>>,
         handle_time: Arc<Mutex<Histogram>>,
         keepalive_tx: Sender<()>,
-        vm_import_url: &'static str,
+        config: Arc<Mutex<ReporterConfig>>,
+        exporter: Box<dyn Exporter>,
     ) -> Self {
         Self {
+            target,
             client_metrics,
             handle_time,
             keepalive_tx,
-            vm_import_url,
+            config,
+            started: SystemTime::now(),
+            jitter: (0, Duration::from_millis(0)),
+            exporter,
+        }
+    }
+
+    /// offset is re-picked only when jitter setting changes
+    fn jitter_offset(&mut self, jitter_ms: u64) -> Duration {
+        if self.jitter.0 != jitter_ms {
+            let offset = Duration::from_millis(rand::thread_rng().gen_range(0..=jitter_ms));
+            info!(
+                "Reports to {} are shifted by {}ms",
+                self.target,
+                offset.as_millis()
+            );
+            self.jitter = (jitter_ms, offset);
         }
+        self.jitter.1
+    }
+
+    fn next_report_delay(&mut self) -> Duration {
+        // config may be reloaded between reports
+        let config = self.config.lock().unwrap().clone();
+        let base = if config.align_to_period {
+            UNIX_EPOCH
+        } else {
+            self.started
+        };
+        let offset = self.jitter_offset(config.jitter_ms);
+        next_report_delay(
+            SystemTime::now(),
+            base,
+            Duration::from_secs(config.period_seconds),
+            offset,
+        )
     }
 
     pub async fn run(&mut self) -> Result<(), RegistryError> {
         loop {
-            trace!("Starting report");
+            tokio::time::sleep(self.next_report_delay()).await;
+            trace!("Starting report to {}", self.target);
             let start = Instant::now();
 
             match self.keepalive_tx.send(()) {
@@ -76,57 +110,157 @@ impl Reporter<'_> {
                 Ok(_) => {}
             }
 
-            self.tick().await?;
-            info!("Report took {}ms", start.elapsed().as_millis());
-            thread::sleep(Duration::from_secs(REPORT_PERIOD_SECONDS));
+            self.tick().await;
+            info!(
+                "Report to {} took {}ms",
+                self.target,
+                start.elapsed().as_millis()
+            );
         }
     }
 
-    async fn tick(&mut self) -> Result<(), RegistryError> {
-        // todo this is very very bad (tons of allocations)
-        // maybe write all the data to the tempfile and then use it as request body?
-        // or integrate hyper::body::Body::channel normally?
-        let mut report = String::new();
-        let locked = self.handle_time.lock().unwrap();
-        for row in locked.serialize_prometheus() {
-            report.push_str(&row);
+    /// collections are taken under a single lock, so report is consistent
+    /// they are shared with processor, which copies one only when it's updated during the report
+    fn snapshot(&self, filter: &TargetFilter) -> Snapshot {
+        let handle_time = self.handle_time.lock().unwrap().clone();
+        let collections = self
+            .client_metrics
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|hc| hc.matches(filter))
+            .cloned()
+            .collect();
+        Snapshot {
+            timestamp: SystemTime::now(),
+            handle_time,
+            collections,
         }
-        std::mem::drop(locked);
+    }
 
-        let locked = self.client_metrics.lock().unwrap();
-        for hc in locked.values() {
-            for row in hc.serialize_prometheus() {
-                report.push_str(&row);
-            }
+    async fn tick(&mut self) {
+        // config may be reloaded between reports
+        let config = self.config.lock().unwrap().clone();
+        let snapshot = self.snapshot(&config.filter);
+        self.exporter.export(&config, &snapshot).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::defs::{ReporterConfig, TargetFilter};
+    use crate::constants as c;
+    use crate::metrics::histogram::metric::Histogram;
+    use crate::metrics::tag::Tag;
+    use crate::workers::registry::exporter::{ExportFuture, Exporter, Snapshot};
+    use crate::workers::registry::hc::HistogramCollection;
+    use crate::workers::registry::reporter::{next_report_delay, Reporter};
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+    use std::sync::mpsc::channel;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// keeps every snapshot it gets
+    struct MemoryExporter {
+        snapshots: Rc<RefCell<Vec<Snapshot>>>,
+    }
+
+    impl Exporter for MemoryExporter {
+        fn export<'a>(
+            &'a mut self,
+            _config: &'a ReporterConfig,
+            snapshot: &'a Snapshot,
+        ) -> ExportFuture<'a> {
+            self.snapshots.borrow_mut().push(snapshot.clone());
+            Box::pin(async {})
         }
-        std::mem::drop(locked);
-
-        let client = Client::new();
-        // this is not recoverable - no sense in working if there is nowhere to report
-        // also config validation should check that url is OK
-        let mut url = Url::parse(self.vm_import_url).unwrap();
-        for (key, value) in ADDITIONAL_LABELS.iter() {
-            url.query_pairs_mut().append_pair(key, value);
+    }
+
+    fn realm(name: &str) -> TargetFilter {
+        TargetFilter {
+            realms: vec![name.to_string()],
+            applications: Vec::new(),
         }
-        let req = Request::post(self.vm_import_url).body(Body::from(report));
-
-        match req {
-            Ok(request) => {
-                let response = client.request(request).await;
-                match response {
-                    Ok(result) => {
-                        info!("got {} from vm", result.status().as_u16())
-                    }
-                    Err(err) => {
-                        error!("Error during request {:?}", err);
-                    }
-                }
-            }
-            Err(err) => {
-                error!("Error during building request {:?}", err);
-            }
-        };
+    }
+
+    #[test]
+    fn test_next_report_delay() {
+        let period = Duration::from_secs(15);
+        let at = |ms: u64| UNIX_EPOCH + Duration::from_millis(ms);
+        let no_offset = Duration::from_millis(0);
+        let offset = Duration::from_millis(500);
+
+        let cases = vec![
+            (at(0), no_offset, 15_000),
+            (at(1_000), no_offset, 14_000),
+            (at(14_999), no_offset, 1),
+            (at(15_000), no_offset, 15_000),
+            (at(0), offset, 500),
+            (at(500), offset, 15_000),
+            (at(15_000), offset, 500),
+            (at(15_600), offset, 14_900),
+        ];
+
+        for (now, offset, expected) in cases {
+            assert_eq!(
+                next_report_delay(now, UNIX_EPOCH, period, offset),
+                Duration::from_millis(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn test_next_report_delay_from_start() {
+        let period = Duration::from_secs(10);
+        let started = UNIX_EPOCH + Duration::from_millis(1_234);
+        let now = started + Duration::from_millis(12_000);
+
+        assert_eq!(
+            next_report_delay(now, started, period, Duration::from_millis(0)),
+            Duration::from_millis(8_000)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tick_exports_filtered_snapshot() {
+        let mut client_metrics = HashMap::new();
+        for (key, name) in [(1, "prod"), (2, "stage")] {
+            let tags = vec![Tag {
+                key: c::REALM_TAG_NAME.to_string(),
+                value: name.to_string(),
+            }];
+            client_metrics.insert(key, Arc::new(HistogramCollection::new(tags)));
+        }
+        let mut config: ReporterConfig =
+            serde_yaml::from_str("vm_import_url: http://vm:8428/api/v1/import/prometheus").unwrap();
+        config.filter = realm("prod");
+        let snapshots = Rc::new(RefCell::new(Vec::new()));
+        let (keepalive_tx, _keepalive_rx) = channel();
+        let mut reporter = Reporter::new(
+            "memory".to_string(),
+            Arc::new(Mutex::new(client_metrics)),
+            Arc::new(Mutex::new(Histogram::new("handle".to_string(), Vec::new()))),
+            keepalive_tx,
+            Arc::new(Mutex::new(config)),
+            Box::new(MemoryExporter {
+                snapshots: snapshots.clone(),
+            }),
+        );
+
+        reporter.tick().await;
+        reporter.tick().await;
 
-        Ok(())
+        let snapshots = snapshots.borrow();
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[0].collections.len(), 1);
+        assert!(snapshots[0].collections[0].matches(&realm("prod")));
+        assert!(snapshots[0].timestamp <= snapshots[1].timestamp);
+        // unchanged collection isn't copied for every report
+        assert!(Arc::ptr_eq(
+            &snapshots[0].collections[0],
+            &snapshots[1].collections[0]
+        ));
     }
 }
@@ -0,0 +1,263 @@
+use crate::config::defs::ScrapeConfig;
+use crate::constants as c;
+use crate::metrics::histogram::metric::Histogram;
+use crate::metrics::traits::PrometheusMetric;
+use crate::workers::registry::hc::HistogramCollection;
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::{error, info};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const METRICS_PATH: &str = "/metrics";
+/// text exposition format understood by Prometheus
+const EXPOSITION_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// bound before registry is started, so bind errors stop the agent like listener ones
+pub fn bind(config: &ScrapeConfig) -> std::io::Result<TcpListener> {
+    let listener = TcpListener::bind(SocketAddr::new(config.address, config.port))?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+/// serves current state of collections to Prometheus, nothing is reset on scrape
+pub struct ScrapeServer {
+    listener: TcpListener,
+    client_metrics: Arc<Mutex<HashMap<u64, Arc<HistogramCollection>>>>,
+    handle_time: Arc<Mutex<Histogram>>,
+}
+
+impl ScrapeServer {
+    pub fn new(
+        listener: TcpListener,
+        client_metrics: Arc<Mutex<HashMap<u64, Arc<HistogramCollection>>>>,
+        handle_time: Arc<Mutex<Histogram>>,
+    ) -> Self {
+        Self {
+            listener,
+            client_metrics,
+            handle_time,
+        }
+    }
+
+    /// blocks current thread serving scrapes
+    pub fn run(self) {
+        info!(
+            "Starting scrape endpoint thread with id: {:?}, listening to {:?}",
+            thread::current().id(),
+            self.listener.local_addr().unwrap()
+        );
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Unable to create runtime");
+
+        let client_metrics = self.client_metrics;
+        let handle_time = self.handle_time;
+        let listener = self.listener;
+        runtime.block_on(async move {
+            let make_service = make_service_fn(move |_| {
+                let client_metrics = client_metrics.clone();
+                let handle_time = handle_time.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req| {
+                        handle(req, client_metrics.clone(), handle_time.clone())
+                    }))
+                }
+            });
+
+            match Server::from_tcp(listener) {
+                Ok(builder) => {
+                    if let Err(err) = builder.serve(make_service).await {
+                        error!("Scrape endpoint stopped {:?}", err);
+                    }
+                }
+                Err(err) => error!("Unable to start scrape endpoint {:?}", err),
+            }
+        });
+    }
+}
+
+/// `# HELP` text of histogram family, every histogram is tracked in microseconds
+fn help(name: &str) -> &'static str {
+    match name {
+        c::ACTION_METRIC_NAME => "Duration of action spans in microseconds.",
+        c::HANDLE_TIME_METRIC_NAME => "Time agent took to process a message in microseconds.",
+        _ => "Duration in microseconds.",
+    }
+}
+
+/// rows of every histogram family go together, after its `# HELP` and `# TYPE` lines
+fn render_families(families: BTreeMap<String, String>) -> String {
+    let mut body = String::new();
+    for (name, rows) in families {
+        body.push_str(&format!("# HELP {} {}\n", name, help(&name)));
+        body.push_str(&format!("# TYPE {} histogram\n", name));
+        body.push_str(&rows);
+    }
+    body
+}
+
+fn add_histogram(families: &mut BTreeMap<String, String>, histogram: &Histogram) {
+    families
+        .entry(histogram.name().to_string())
+        .or_default()
+        .push_str(&histogram.serialize_exposition().concat());
+}
+
+/// collections are locked one at a time, so processor isn't blocked for the whole scrape
+fn render(
+    client_metrics: &Mutex<HashMap<u64, Arc<HistogramCollection>>>,
+    handle_time: &Mutex<Histogram>,
+) -> String {
+    let mut families = BTreeMap::new();
+    add_histogram(&mut families, &handle_time.lock().unwrap());
+    let keys: Vec<u64> = client_metrics.lock().unwrap().keys().cloned().collect();
+    for key in keys {
+        if let Some(hc) = client_metrics.lock().unwrap().get(&key) {
+            for histogram in hc.histograms() {
+                add_histogram(&mut families, histogram);
+            }
+        }
+    }
+    render_families(families)
+}
+
+async fn handle(
+    req: Request<Body>,
+    client_metrics: Arc<Mutex<HashMap<u64, Arc<HistogramCollection>>>>,
+    handle_time: Arc<Mutex<Histogram>>,
+) -> Result<Response<Body>, Infallible> {
+    let status = if req.uri().path() != METRICS_PATH {
+        StatusCode::NOT_FOUND
+    } else if req.method() != Method::GET && req.method() != Method::HEAD {
+        StatusCode::METHOD_NOT_ALLOWED
+    } else {
+        let mut response = Response::new(Body::from(render(&client_metrics, &handle_time)));
+        response.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static(EXPOSITION_CONTENT_TYPE),
+        );
+        return Ok(response);
+    };
+
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants as c;
+    use crate::metrics::histogram::metric::Histogram;
+    use crate::workers::registry::hc::HistogramCollection;
+    use crate::workers::registry::scrape::{handle, render};
+    use hyper::body::to_bytes;
+    use hyper::{Body, Method, Request, StatusCode};
+    use palantir_proto::palantir::apm::v1::action::ApmV1Action;
+    use palantir_proto::palantir::request::request::Message as ProtoMessage;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn test_handle() {
+        let client_metrics = Arc::new(Mutex::new(HashMap::new()));
+        let mut histogram = Histogram::new("handle".to_string(), Vec::new());
+        histogram.track(10);
+        let handle_time = Arc::new(Mutex::new(histogram));
+        let request = |method: Method, path: &str| {
+            Request::builder()
+                .method(method)
+                .uri(path)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = handle(
+            request(Method::GET, "/metrics"),
+            client_metrics.clone(),
+            handle_time.clone(),
+        )
+        .await
+        .unwrap();
+        let not_found = handle(
+            request(Method::GET, "/"),
+            client_metrics.clone(),
+            handle_time.clone(),
+        )
+        .await
+        .unwrap();
+        let not_allowed = handle(
+            request(Method::POST, "/metrics"),
+            client_metrics,
+            handle_time,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.starts_with(
+            "# HELP handle Duration in microseconds.\n# TYPE handle histogram\nhandle_bucket{le=\"255\"} 1\n"
+        ));
+        assert!(body.ends_with("handle_count 1\nhandle_sum 10\n"));
+        assert_eq!(not_found.status(), StatusCode::NOT_FOUND);
+        assert_eq!(not_allowed.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[test]
+    fn test_render_families() {
+        let mut client_metrics = HashMap::new();
+        for (key, application) in [(1, "web"), (2, "worker")] {
+            let msg = ProtoMessage::ApmV1Action(ApmV1Action {
+                realm: "prod".to_string(),
+                application: application.to_string(),
+                application_hash: "3fde5".to_string(),
+                action_kind: "http".to_string(),
+                action_name: "index".to_string(),
+                total_us: 10,
+                additional_dimensions: vec![],
+                measurements: vec![],
+            });
+            let mut hc = HistogramCollection::from(&msg);
+            hc.process(msg);
+            client_metrics.insert(key, Arc::new(hc));
+        }
+        let handle_time = Histogram::new(c::HANDLE_TIME_METRIC_NAME.to_string(), Vec::new());
+
+        let body = render(&Mutex::new(client_metrics), &Mutex::new(handle_time));
+        let lines: Vec<&str> = body.lines().collect();
+
+        let types: Vec<&str> = lines
+            .iter()
+            .filter(|line| line.starts_with("# TYPE"))
+            .cloned()
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                "# TYPE palantir_apm histogram",
+                "# TYPE request_handle_time histogram"
+            ]
+        );
+        // rows of both collections go right after header of their family
+        let apm_rows: Vec<usize> = lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| line.starts_with("palantir_apm"))
+            .map(|(index, _)| index)
+            .collect();
+        assert_eq!(
+            lines[0],
+            "# HELP palantir_apm Duration of action spans in microseconds."
+        );
+        assert_eq!(apm_rows, (2..2 + apm_rows.len()).collect::<Vec<_>>());
+        assert!(body.contains("palantir_application=\"web\""));
+        assert!(body.contains("palantir_application=\"worker\""));
+    }
+}
@@ -0,0 +1,103 @@
+use crate::metrics::counter::Counter;
+use crate::metrics::gauge::Gauge;
+use crate::metrics::sample::Sample;
+use crate::metrics::tag::Tag;
+use crate::metrics::traits::PrometheusMetric;
+
+fn push_responses(target: &str, class: &str) -> Counter {
+    Counter::new(
+        "palantir_agent_push_responses_total".to_string(),
+        vec![Tag::new("class", class), Tag::new("target", target)],
+    )
+}
+
+/// agent's own health, reported along with client metrics
+/// every target keeps its own, series are told apart by `target` label
+pub struct SelfMetrics {
+    pub spool_size_bytes: Gauge,
+    pub spool_payloads: Gauge,
+    /// payloads lost because there was no spool or it was full
+    pub dropped_payloads: Counter,
+    pub push_retries: Counter,
+    pub push_failures: Counter,
+    /// per push attempt, network errors and timeouts count as retryable
+    pub push_success: Counter,
+    pub push_retryable: Counter,
+    pub push_permanent: Counter,
+}
+
+impl SelfMetrics {
+    pub fn new(target: &str) -> Self {
+        let gauge = |name: &str| Gauge::new(name.to_string(), vec![Tag::new("target", target)]);
+        let counter = |name: &str| Counter::new(name.to_string(), vec![Tag::new("target", target)]);
+        Self {
+            spool_size_bytes: gauge("palantir_agent_spool_size_bytes"),
+            spool_payloads: gauge("palantir_agent_spool_payloads"),
+            dropped_payloads: counter("palantir_agent_dropped_payloads_total"),
+            push_retries: counter("palantir_agent_push_retries_total"),
+            push_failures: counter("palantir_agent_push_failures_total"),
+            push_success: push_responses(target, "success"),
+            push_retryable: push_responses(target, "retryable"),
+            push_permanent: push_responses(target, "permanent"),
+        }
+    }
+}
+
+impl PrometheusMetric for SelfMetrics {
+    fn serialize_prometheus(&self) -> Vec<String> {
+        let mut result = Vec::new();
+        result.extend(self.spool_size_bytes.serialize_prometheus());
+        result.extend(self.spool_payloads.serialize_prometheus());
+        result.extend(self.dropped_payloads.serialize_prometheus());
+        result.extend(self.push_retries.serialize_prometheus());
+        result.extend(self.push_failures.serialize_prometheus());
+        result.extend(self.push_success.serialize_prometheus());
+        result.extend(self.push_retryable.serialize_prometheus());
+        result.extend(self.push_permanent.serialize_prometheus());
+        result
+    }
+
+    fn samples(&self) -> Vec<Sample> {
+        let mut result = Vec::new();
+        result.extend(self.spool_size_bytes.samples());
+        result.extend(self.spool_payloads.samples());
+        result.extend(self.dropped_payloads.samples());
+        result.extend(self.push_retries.samples());
+        result.extend(self.push_failures.samples());
+        result.extend(self.push_success.samples());
+        result.extend(self.push_retryable.samples());
+        result.extend(self.push_permanent.samples());
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::traits::PrometheusMetric;
+    use crate::workers::registry::self_metrics::SelfMetrics;
+
+    #[test]
+    fn test_serialize() {
+        let mut metrics = SelfMetrics::new("default");
+        metrics.spool_size_bytes.set(2048);
+        metrics.dropped_payloads.inc();
+        metrics.dropped_payloads.add(2);
+        metrics.push_permanent.inc();
+
+        let rows = metrics.serialize_prometheus();
+
+        assert_eq!(
+            rows[0],
+            "palantir_agent_spool_size_bytes{target=\"default\"} 2048\n"
+        );
+        assert_eq!(
+            rows[2],
+            "palantir_agent_dropped_payloads_total{target=\"default\"} 3\n"
+        );
+        assert_eq!(
+            rows[7],
+            "palantir_agent_push_responses_total{class=\"permanent\",target=\"default\"} 1\n"
+        );
+        assert_eq!(rows.len(), 8);
+    }
+}
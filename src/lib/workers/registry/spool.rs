@@ -0,0 +1,344 @@
+use crate::config::defs::{Compression, ExporterKind, SpoolConfig};
+use crate::workers::registry::payload::PART_FILE_EXTENSION;
+use log::{info, warn};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{ErrorKind, Result as IOResult};
+use std::path::{Path, PathBuf};
+
+/// protocol and compression of spooled payload, kept in file extension
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Encoding {
+    pub kind: ExporterKind,
+    pub compression: Compression,
+}
+
+/// extensions of protocols other than VM import one, which goes without it
+const KIND_EXTENSIONS: [(ExporterKind, &str); 5] = [
+    (ExporterKind::RemoteWrite, "remote_write"),
+    (ExporterKind::Influx, "influx"),
+    (ExporterKind::Graphite, "graphite"),
+    (ExporterKind::Otlp, "otlp"),
+    (ExporterKind::File, "file"),
+];
+
+fn compression_extension(compression: Compression) -> Option<&'static str> {
+    match compression {
+        Compression::None => None,
+        Compression::Gzip => Some("gz"),
+        Compression::Zstd => Some("zst"),
+    }
+}
+
+/// e.g. `influx.gz`, VM import payloads are named as they were before protocol was kept
+fn extension(encoding: Encoding) -> String {
+    let compression = compression_extension(encoding.compression);
+    let kind = KIND_EXTENSIONS
+        .iter()
+        .find(|(kind, _)| *kind == encoding.kind)
+        .map(|(_, extension)| *extension);
+    match (kind, compression) {
+        (None, None) => "prom".to_string(),
+        (None, Some(compression)) => compression.to_string(),
+        (Some(kind), None) => kind.to_string(),
+        (Some(kind), Some(compression)) => format!("{}.{}", kind, compression),
+    }
+}
+
+fn encoding(extension: &str) -> Option<Encoding> {
+    let (kind, compression) = match extension.split_once('.') {
+        Some((kind, compression)) => (kind, Some(compression)),
+        None => (extension, None),
+    };
+    let compression_of = |extension: Option<&str>| {
+        [Compression::None, Compression::Gzip, Compression::Zstd]
+            .iter()
+            .copied()
+            .find(|compression| compression_extension(*compression) == extension)
+    };
+    let vm_import = |compression| {
+        Some(Encoding {
+            kind: ExporterKind::VmImport,
+            compression,
+        })
+    };
+    match (kind, compression) {
+        ("prom", None) => vm_import(Compression::None),
+        ("gz", None) => vm_import(Compression::Gzip),
+        ("zst", None) => vm_import(Compression::Zstd),
+        (kind, compression) => Some(Encoding {
+            kind: KIND_EXTENSIONS
+                .iter()
+                .find(|(_, extension)| *extension == kind)
+                .map(|(kind, _)| *kind)?,
+            compression: compression_of(compression)?,
+        }),
+    }
+}
+
+/// size-capped directory of payloads that weren't pushed, replayed oldest first
+/// payloads are named by sequence number so order survives restarts
+pub struct Spool {
+    dir: PathBuf,
+    max_size_bytes: u64,
+    size_bytes: u64,
+    /// (path, size, encoding), oldest first
+    payloads: VecDeque<(PathBuf, u64, Encoding)>,
+    next_seq: u64,
+}
+
+impl Spool {
+    /// picks up payloads left by the previous run
+    pub fn open(config: &SpoolConfig) -> IOResult<Self> {
+        fs::create_dir_all(&config.path)?;
+
+        let mut found: Vec<(u64, PathBuf, u64, Encoding)> = Vec::new();
+        for entry in fs::read_dir(&config.path)? {
+            let path = entry?.path();
+            // payloads and report parts that were being written when previous run stopped
+            if path.extension().and_then(|ext| ext.to_str()) == Some(PART_FILE_EXTENSION) {
+                if let Err(err) = fs::remove_file(&path) {
+                    warn!("Unable to remove unfinished payload {:?}, {:?}", path, err);
+                }
+                continue;
+            }
+            // `{seq}.{extension}`, anything else isn't a payload
+            let name = match path.file_name().and_then(|name| name.to_str()) {
+                Some(name) => name,
+                None => continue,
+            };
+            let (seq, encoding) = match name.split_once('.') {
+                Some((seq, extension)) => match (seq.parse::<u64>(), encoding(extension)) {
+                    (Ok(seq), Some(encoding)) => (seq, encoding),
+                    _ => continue,
+                },
+                None => continue,
+            };
+            let size = fs::metadata(&path)?.len();
+            found.push((seq, path, size, encoding));
+        }
+        found.sort_by_key(|(seq, _, _, _)| *seq);
+
+        let next_seq = found.last().map(|(seq, _, _, _)| seq + 1).unwrap_or(0);
+        let size_bytes = found.iter().map(|(_, _, size, _)| size).sum();
+        let payloads = found
+            .into_iter()
+            .map(|(_, path, size, encoding)| (path, size, encoding))
+            .collect::<VecDeque<_>>();
+        if !payloads.is_empty() {
+            info!(
+                "Found {} spooled payloads ({} bytes) in {:?}",
+                payloads.len(),
+                size_bytes,
+                config.path
+            );
+        }
+
+        Ok(Self {
+            dir: config.path.clone(),
+            max_size_bytes: config.max_size_bytes,
+            size_bytes,
+            payloads,
+            next_seq,
+        })
+    }
+
+    /// Ok -> number of payloads dropped to stay within size limit
+    /// payload larger than the limit is dropped itself
+    pub fn push(&mut self, payload: &[u8], encoding: Encoding) -> IOResult<u64> {
+        self.store(payload.len() as u64, encoding, |path| {
+            // rename is atomic, so half-written payload is never replayed
+            let tmp_path = path.with_extension(PART_FILE_EXTENSION);
+            fs::write(&tmp_path, payload)?;
+            fs::rename(&tmp_path, path)
+        })
+    }
+
+    /// same as `push`, for payload already written to a file in spool directory
+    pub fn push_file(&mut self, file: &Path, encoding: Encoding) -> IOResult<u64> {
+        let size = fs::metadata(file)?.len();
+        self.store(size, encoding, |path| fs::rename(file, path))
+    }
+
+    fn store<F>(&mut self, size: u64, encoding: Encoding, write: F) -> IOResult<u64>
+    where
+        F: FnOnce(&Path) -> IOResult<()>,
+    {
+        if size > self.max_size_bytes {
+            warn!("Payload of {} bytes doesn't fit into spool, dropping", size);
+            return Ok(1);
+        }
+
+        let mut dropped = 0;
+        while self.size_bytes + size > self.max_size_bytes {
+            self.pop()?;
+            dropped += 1;
+        }
+        if dropped > 0 {
+            warn!("Spool is full, dropped {} oldest payloads", dropped);
+        }
+
+        let path = self
+            .dir
+            .join(format!("{:020}.{}", self.next_seq, extension(encoding)));
+        write(&path)?;
+
+        self.next_seq += 1;
+        self.size_bytes += size;
+        self.payloads.push_back((path, size, encoding));
+
+        Ok(dropped)
+    }
+
+    pub fn oldest(&self) -> Option<IOResult<(Vec<u8>, Encoding)>> {
+        self.payloads
+            .front()
+            .map(|(path, _, encoding)| fs::read(path).map(|payload| (payload, *encoding)))
+    }
+
+    /// removes the oldest payload, it stays in place if file can't be removed
+    pub fn pop(&mut self) -> IOResult<()> {
+        if let Some((path, size, _)) = self.payloads.front() {
+            match fs::remove_file(path) {
+                Ok(()) => (),
+                Err(err) if err.kind() == ErrorKind::NotFound => (),
+                Err(err) => return Err(err),
+            }
+            self.size_bytes -= size;
+            self.payloads.pop_front();
+        }
+        Ok(())
+    }
+
+    pub fn size_bytes(&self) -> u64 {
+        self.size_bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.payloads.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::defs::{Compression, ExporterKind, SpoolConfig};
+    use crate::workers::registry::spool::{extension, Encoding, Spool};
+    use std::path::PathBuf;
+
+    fn encoding(kind: ExporterKind, compression: Compression) -> Encoding {
+        Encoding { kind, compression }
+    }
+
+    fn spool_config(name: &str, max_size_bytes: u64) -> SpoolConfig {
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("palantir-spool-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        SpoolConfig {
+            path,
+            max_size_bytes,
+        }
+    }
+
+    #[test]
+    fn test_replay_in_order_after_reopen() {
+        let config = spool_config("order", 1024);
+        let mut spool = Spool::open(&config).unwrap();
+        let first = encoding(ExporterKind::VmImport, Compression::None);
+        let second = encoding(ExporterKind::RemoteWrite, Compression::None);
+        let third = encoding(ExporterKind::Influx, Compression::Zstd);
+        spool.push(b"first", first).unwrap();
+        spool.push(b"second", second).unwrap();
+        drop(spool);
+
+        let mut spool = Spool::open(&config).unwrap();
+        assert_eq!(spool.len(), 2);
+        assert_eq!(spool.size_bytes(), 11);
+        spool.push(b"third", third).unwrap();
+
+        let mut replayed = Vec::new();
+        while let Some(payload) = spool.oldest() {
+            replayed.push(payload.unwrap());
+            spool.pop().unwrap();
+        }
+
+        assert_eq!(
+            replayed,
+            vec![
+                (b"first".to_vec(), first),
+                (b"second".to_vec(), second),
+                (b"third".to_vec(), third)
+            ]
+        );
+        assert_eq!(spool.size_bytes(), 0);
+        std::fs::remove_dir_all(&config.path).unwrap();
+    }
+
+    #[test]
+    fn test_drop_oldest_when_full() {
+        let config = spool_config("full", 10);
+        let mut spool = Spool::open(&config).unwrap();
+        let vm_import = encoding(ExporterKind::VmImport, Compression::None);
+
+        assert_eq!(spool.push(b"aaaa", vm_import).unwrap(), 0);
+        assert_eq!(spool.push(b"bbbb", vm_import).unwrap(), 0);
+        assert_eq!(spool.push(b"cccc", vm_import).unwrap(), 1);
+        assert_eq!(spool.push(b"too large payload", vm_import).unwrap(), 1);
+
+        assert_eq!(spool.len(), 2);
+        assert_eq!(spool.oldest().unwrap().unwrap().0, b"bbbb".to_vec());
+        std::fs::remove_dir_all(&config.path).unwrap();
+    }
+
+    #[test]
+    fn test_push_file() {
+        let config = spool_config("file", 1024);
+        let mut spool = Spool::open(&config).unwrap();
+        let part = config.path.join("part-1.tmp");
+        std::fs::write(&part, b"streamed").unwrap();
+
+        let gzip = encoding(ExporterKind::VmImport, Compression::Gzip);
+        spool.push_file(&part, gzip).unwrap();
+
+        assert!(!part.exists());
+        assert_eq!(spool.size_bytes(), 8);
+        // unfinished parts are removed on open, spooled ones are kept
+        std::fs::write(&part, b"unfinished").unwrap();
+        let spool = Spool::open(&config).unwrap();
+        assert!(!part.exists());
+        assert_eq!(
+            spool.oldest().unwrap().unwrap(),
+            (b"streamed".to_vec(), gzip)
+        );
+        std::fs::remove_dir_all(&config.path).unwrap();
+    }
+
+    #[test]
+    fn test_extensions() {
+        for kind in [
+            ExporterKind::VmImport,
+            ExporterKind::RemoteWrite,
+            ExporterKind::Influx,
+            ExporterKind::Graphite,
+            ExporterKind::Otlp,
+            ExporterKind::File,
+        ] {
+            for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+                let encoding = encoding(kind, compression);
+                assert_eq!(
+                    crate::workers::registry::spool::encoding(&extension(encoding)),
+                    Some(encoding)
+                );
+            }
+        }
+        // VM import payloads spooled before protocol was kept
+        assert_eq!(
+            extension(encoding(ExporterKind::VmImport, Compression::Gzip)),
+            "gz"
+        );
+        assert_eq!(
+            extension(encoding(ExporterKind::RemoteWrite, Compression::None)),
+            "remote_write"
+        );
+        assert_eq!(crate::workers::registry::spool::encoding("tmp"), None);
+    }
+}
@@ -0,0 +1,103 @@
+use crate::config::defs::{ExporterKind, ReporterConfig};
+use crate::metrics::sample::Sample;
+use crate::metrics::traits::PrometheusMetric;
+use crate::workers::registry::delivery::{Delivery, Report};
+use crate::workers::registry::exporter::{ExportFuture, Exporter, Snapshot};
+use crate::workers::registry::writer::{ReportWriter, SentPart};
+use hyper::HeaderMap;
+use std::io::Result as IOResult;
+use url::Url;
+
+/// extra labels are passed to VM as `extra_label` query params, VM adds them to every series
+fn import_url(config: &ReporterConfig) -> Url {
+    // config validation checks that url is OK
+    let mut url = Url::parse(config.vm_import_url.as_deref().unwrap_or_default()).unwrap();
+    for (key, value) in config.extra_labels.iter() {
+        url.query_pairs_mut()
+            .append_pair("extra_label", &format!("{}={}", key, value));
+    }
+    url
+}
+
+/// serializes report into parts, every part is pushed as soon as it's started
+/// every row is stamped with report time, so replayed payloads keep their original time
+async fn stream_report(
+    mut writer: ReportWriter,
+    own: Vec<Sample>,
+    snapshot: &Snapshot,
+) -> IOResult<Vec<SentPart>> {
+    let suffix = format!(" {}\n", snapshot.timestamp_ms());
+
+    let mut rows: Vec<String> = own.iter().map(Sample::to_exposition).collect();
+    rows.extend(snapshot.handle_time.serialize_prometheus());
+    for row in rows {
+        writer.write_row(row.trim_end(), &suffix).await?;
+    }
+    for hc in snapshot.collections.iter() {
+        for row in hc.serialize_prometheus() {
+            writer.write_row(row.trim_end(), &suffix).await?;
+        }
+    }
+
+    writer.finish().await
+}
+
+// TODO add metrics about victoriametrics response time
+/// pushes Prometheus text to VictoriaMetrics `/api/v1/import/prometheus`
+/// payloads that couldn't be pushed are spooled and replayed before the next report
+pub struct VmExporter {
+    delivery: Delivery,
+}
+
+impl VmExporter {
+    pub fn new(target: String) -> Self {
+        Self {
+            delivery: Delivery::new(target, ExporterKind::VmImport),
+        }
+    }
+
+    async fn push(&mut self, config: &ReporterConfig, snapshot: &Snapshot) {
+        let url = import_url(config).to_string();
+        self.delivery
+            .push_report(config, url, HeaderMap::new(), |pusher, own| async move {
+                stream_report(ReportWriter::new(config, pusher), own, snapshot)
+                    .await
+                    .map(Report::Streamed)
+            })
+            .await;
+    }
+}
+
+impl Exporter for VmExporter {
+    fn export<'a>(
+        &'a mut self,
+        config: &'a ReporterConfig,
+        snapshot: &'a Snapshot,
+    ) -> ExportFuture<'a> {
+        Box::pin(self.push(config, snapshot))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::defs::ReporterConfig;
+    use crate::workers::registry::vm::import_url;
+
+    #[test]
+    fn test_import_url_extra_labels() {
+        let mut config: ReporterConfig =
+            serde_yaml::from_str("vm_import_url: http://vm:8428/api/v1/import/prometheus?x=1")
+                .unwrap();
+        config
+            .extra_labels
+            .insert("pod".to_string(), "web-1".to_string());
+        config
+            .extra_labels
+            .insert("dc".to_string(), "eu 1".to_string());
+
+        assert_eq!(
+            import_url(&config).as_str(),
+            "http://vm:8428/api/v1/import/prometheus?x=1&extra_label=dc%3Deu+1&extra_label=pod%3Dweb-1"
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use crate::config::defs::{Compression, ReporterConfig};
+use crate::workers::registry::encoder::Encoder;
+use crate::workers::registry::payload::{PartFile, Payload};
+use crate::workers::registry::push::{PushError, Pusher};
+use hyper::body::{Bytes, Sender as BodySender};
+use hyper::Body;
+use log::trace;
+use std::io::Result as IOResult;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
+/// encoded report is sent in chunks of about this size
+const STREAM_CHUNK_BYTES: usize = 64 * 1024;
+
+/// report part being serialized and, if pushed, streamed to target
+struct Part {
+    encoder: Encoder,
+    /// encoded output is written there for retries and spool
+    file: PartFile,
+    output: File,
+    /// size before encoding, compared to `max_payload_bytes`
+    size: usize,
+    sender: Option<BodySender>,
+    push: Option<JoinHandle<Result<(), PushError>>>,
+}
+
+impl Part {
+    async fn send(&mut self, chunk: Vec<u8>) -> IOResult<()> {
+        self.output.write_all(&chunk).await?;
+        if let Some(body) = self.sender.as_mut() {
+            // chunk is in the file anyway, it's needed for retry
+            if body.send_data(Bytes::from(chunk)).await.is_err() {
+                trace!("Request body dropped, report is kept for retry");
+                self.sender = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// dropping sender ends request body
+    async fn finish(mut self) -> IOResult<SentPart> {
+        let rest = self.encoder.finish()?;
+        self.send(rest).await?;
+        self.output.flush().await?;
+        Ok(SentPart {
+            payload: Payload::File(self.file),
+            push: self.push,
+        })
+    }
+}
+
+/// `push` is None if part wasn't pushed
+pub struct SentPart {
+    pub payload: Payload,
+    pub push: Option<JoinHandle<Result<(), PushError>>>,
+}
+
+/// splits report into parts of at most `max_payload_bytes` (before encoding)
+/// at most `slots` parts are pushed at once, serialization waits for a free slot
+/// parts are written to files in spool directory (or temp one), so they can be moved to spool
+pub struct ReportWriter {
+    dir: PathBuf,
+    compression: Compression,
+    pusher: Option<Arc<Pusher>>,
+    slots: Arc<Semaphore>,
+    max_payload_bytes: usize,
+    current: Option<Part>,
+    done: Vec<SentPart>,
+}
+
+impl ReportWriter {
+    /// parts are only encoded, not pushed, if `pusher` is None
+    pub fn new(config: &ReporterConfig, pusher: Option<Arc<Pusher>>) -> Self {
+        Self {
+            dir: config
+                .spool
+                .as_ref()
+                .map(|spool| spool.path.clone())
+                .unwrap_or_else(std::env::temp_dir),
+            compression: config.compression,
+            pusher,
+            slots: Arc::new(Semaphore::new(config.max_parallel_pushes)),
+            max_payload_bytes: config
+                .max_payload_bytes
+                .map(|limit| limit as usize)
+                .unwrap_or(usize::MAX),
+            current: None,
+            done: Vec::new(),
+        }
+    }
+
+    pub async fn write_row(&mut self, row: &str, suffix: &str) -> IOResult<()> {
+        let size = row.len() + suffix.len();
+        let full = match &self.current {
+            Some(part) => part.size > 0 && part.size + size > self.max_payload_bytes,
+            None => false,
+        };
+        if full {
+            self.finish_part().await?;
+        }
+        if self.current.is_none() {
+            self.current = Some(self.start_part().await?);
+        }
+
+        let part = self.current.as_mut().unwrap();
+        part.encoder.write(row.as_bytes())?;
+        part.encoder.write(suffix.as_bytes())?;
+        part.size += size;
+        if part.encoder.pending() >= STREAM_CHUNK_BYTES {
+            let chunk = part.encoder.take();
+            part.send(chunk).await?;
+        }
+        Ok(())
+    }
+
+    async fn start_part(&self) -> IOResult<Part> {
+        let encoder = Encoder::new(self.compression)?;
+        let (file, output) = PartFile::create(&self.dir).await?;
+        let (sender, push) = match &self.pusher {
+            Some(pusher) => {
+                let slot = self.slots.clone().acquire_owned().await.unwrap();
+                let (sender, body) = Body::channel();
+                let pusher = pusher.clone();
+                let compression = self.compression;
+                let push = tokio::spawn(async move {
+                    let result = pusher.send(body, compression).await;
+                    drop(slot);
+                    result
+                });
+                (Some(sender), Some(push))
+            }
+            None => (None, None),
+        };
+
+        Ok(Part {
+            encoder,
+            file,
+            output,
+            size: 0,
+            sender,
+            push,
+        })
+    }
+
+    async fn finish_part(&mut self) -> IOResult<()> {
+        if let Some(part) = self.current.take() {
+            self.done.push(part.finish().await?);
+        }
+        Ok(())
+    }
+
+    pub async fn finish(mut self) -> IOResult<Vec<SentPart>> {
+        self.finish_part().await?;
+        Ok(self.done)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::defs::{Compression, ExporterKind, ReporterConfig};
+    use crate::workers::registry::delivery::Delivery;
+    use crate::workers::registry::payload::Payload;
+    use crate::workers::registry::writer::{ReportWriter, SentPart};
+    use hyper::body::{to_bytes, Bytes};
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, HeaderMap, Response, Server};
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::Semaphore;
+
+    fn encoded(part: &SentPart) -> Vec<u8> {
+        match &part.payload {
+            Payload::File(file) => std::fs::read(file.path()).unwrap(),
+            Payload::Memory(_) => panic!("part is expected in a file"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_report_split() {
+        let mut writer = ReportWriter {
+            dir: std::env::temp_dir(),
+            compression: Compression::None,
+            pusher: None,
+            slots: Arc::new(Semaphore::new(1)),
+            max_payload_bytes: 100,
+            current: None,
+            done: Vec::new(),
+        };
+
+        for i in 0..10 {
+            let row = format!("palantir_apm_count{{n=\"{}\"}} 1", i);
+            writer.write_row(&row, " 1000\n").await.unwrap();
+        }
+        let parts = writer.finish().await.unwrap();
+
+        // 33 bytes per row, 3 rows fit into a part
+        let sizes: Vec<usize> = parts.iter().map(|part| encoded(part).len()).collect();
+        assert_eq!(sizes, vec![99, 99, 99, 33]);
+        assert!(parts.iter().all(|part| part.push.is_none()));
+    }
+
+    #[tokio::test]
+    async fn test_streamed_part() {
+        let received: Arc<Mutex<Vec<Bytes>>> = Arc::new(Mutex::new(Vec::new()));
+        let bodies = received.clone();
+        let make_service = make_service_fn(move |_| {
+            let bodies = bodies.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let bodies = bodies.clone();
+                    async move {
+                        let body = to_bytes(req.into_body()).await?;
+                        bodies.lock().unwrap().push(body);
+                        Ok::<_, hyper::Error>(Response::new(Body::empty()))
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}/api/v1/import/prometheus", server.local_addr());
+        tokio::spawn(server);
+        let config: ReporterConfig =
+            serde_yaml::from_str(&format!("vm_import_url: {}", url)).unwrap();
+        let pusher = Delivery::new("vm".to_string(), ExporterKind::VmImport).pusher(
+            &config,
+            url,
+            HeaderMap::new(),
+        );
+        let mut writer = ReportWriter::new(&config, pusher);
+
+        // several stream chunks
+        let mut expected = String::new();
+        for i in 0..10_000 {
+            let row = format!("palantir_apm_count{{n=\"{}\"}} 1", i);
+            writer.write_row(&row, " 1000\n").await.unwrap();
+            expected.push_str(&row);
+            expected.push_str(" 1000\n");
+        }
+        let mut parts = writer.finish().await.unwrap();
+
+        assert_eq!(parts.len(), 1);
+        let part = parts.remove(0);
+        assert_eq!(encoded(&part), expected.as_bytes());
+        part.push.unwrap().await.unwrap().unwrap();
+        assert_eq!(*received.lock().unwrap(), vec![Bytes::from(expected)]);
+    }
+}
@@ -0,0 +1,142 @@
+use crate::workers::server::listeners::{forward_request, is_timeout};
+use log::{trace, warn};
+use palantir_proto::palantir::request::request::Message as ProtoMessage;
+use palantir_proto::prost::bytes::BytesMut;
+use std::fmt::Debug;
+use std::io::{BufReader, ErrorKind, Read};
+use std::sync::mpsc::SyncSender;
+
+/// varint-encoded u64 never takes more than 10 bytes
+const MAX_VARINT_LENGTH: usize = 10;
+
+/// reads length-delimited requests until peer closes the connection
+/// connection is closed on oversized request, there is no telling where the next one starts
+pub fn serve_connection<S: Read, O: Debug>(
+    stream: S,
+    origin: O,
+    buffer_size: usize,
+    tx: SyncSender<ProtoMessage>,
+) {
+    trace!("Accepted connection from {:?}", origin);
+
+    let mut reader = BufReader::new(stream);
+    loop {
+        match read_frame(&mut reader, buffer_size) {
+            Ok(Frame::Request(buf)) => {
+                trace!("Read {} bytes from {:?}", buf.len(), origin);
+                forward_request(buf, &tx);
+            }
+            Ok(Frame::TooLarge(length)) => {
+                warn!(
+                    "Request size too large ({} bytes), closing connection from {:?}",
+                    length, origin
+                );
+                return;
+            }
+            Ok(Frame::Closed) => {
+                trace!("Connection from {:?} closed", origin);
+                return;
+            }
+            Err(err) if is_timeout(&err) => {
+                trace!("Connection from {:?} is idle, closing", origin);
+                return;
+            }
+            Err(err) => {
+                warn!("Unable to read from connection {:?}, closing", err);
+                return;
+            }
+        }
+    }
+}
+
+enum Frame {
+    Request(BytesMut),
+    TooLarge(u64),
+    Closed,
+}
+
+/// reads single varint length prefix and the message that follows it
+fn read_frame<R: Read>(reader: &mut R, buffer_size: usize) -> std::io::Result<Frame> {
+    let length = match read_varint(reader)? {
+        Some(length) => length,
+        None => return Ok(Frame::Closed),
+    };
+
+    // oversized message isn't read at all, the connection is closed instead
+    if length > buffer_size as u64 {
+        return Ok(Frame::TooLarge(length));
+    }
+
+    let mut buf = BytesMut::with_capacity(length as usize);
+    buf.resize(length as usize, 0);
+    reader.read_exact(&mut buf)?;
+
+    Ok(Frame::Request(buf))
+}
+
+/// Ok(None) -> stream closed cleanly before the first byte of varint
+fn read_varint<R: Read>(reader: &mut R) -> std::io::Result<Option<u64>> {
+    let mut value: u64 = 0;
+    let mut byte = [0u8; 1];
+
+    for i in 0..MAX_VARINT_LENGTH {
+        if reader.read(&mut byte)? == 0 {
+            if i == 0 {
+                return Ok(None);
+            }
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        value |= ((byte[0] & 0x7F) as u64) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+
+    Err(std::io::Error::new(
+        ErrorKind::InvalidData,
+        "length prefix is not a valid varint",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::workers::server::listeners::frame::{read_frame, read_varint, Frame};
+    use palantir_proto::palantir::request::Request;
+    use palantir_proto::prost::Message;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_varint() {
+        let mut reader = Cursor::new(vec![0xAC, 0x02]);
+
+        assert_eq!(read_varint(&mut reader).unwrap(), Some(300));
+        assert_eq!(read_varint(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_varint_truncated() {
+        let mut reader = Cursor::new(vec![0xAC]);
+
+        assert!(read_varint(&mut reader).is_err());
+    }
+
+    #[test]
+    fn test_read_frames() {
+        let request = Request { message: None };
+        let mut raw = Vec::new();
+        request.encode_length_delimited(&mut raw).unwrap();
+        raw.extend(&[0x05, 1, 2, 3, 4, 5]);
+        let mut reader = Cursor::new(raw);
+
+        assert!(matches!(
+            read_frame(&mut reader, 4).unwrap(),
+            Frame::Request(_)
+        ));
+        assert!(matches!(
+            read_frame(&mut reader, 4).unwrap(),
+            Frame::TooLarge(5)
+        ));
+        // body of oversized frame is left unread
+        assert_eq!(reader.position(), 2);
+    }
+}
@@ -0,0 +1,517 @@
+use crate::config::defs::HTTPConfig;
+use crate::workers::server::listeners::{registry_gone, StopSignal, STOP_POLL_INTERVAL};
+use hyper::body::HttpBody;
+use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE, RETRY_AFTER};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::{error, info, trace, warn};
+use palantir_proto::palantir::apm::v1::action::ApmV1Action;
+use palantir_proto::palantir::request::request::Message as ProtoMessage;
+use palantir_proto::palantir::request::Request as ProtoRequest;
+use palantir_proto::palantir::shared::measurement::Measurement as ProtoMeasurement;
+use palantir_proto::palantir::shared::tag::Tag as ProtoTag;
+use palantir_proto::prost::bytes::{Buf, BytesMut};
+use palantir_proto::prost::Message;
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::mpsc::{SyncSender, TrySendError};
+use std::thread;
+
+const INGEST_PATH: &str = "/api/v1/ingest";
+
+pub struct HTTPListener {
+    listener: TcpListener,
+    max_body_size: usize,
+    tx: SyncSender<ProtoMessage>,
+}
+
+impl HTTPListener {
+    /// Ok  -> successfully bound to socket
+    /// Err -> was unable to bind to socket
+    pub fn new(config: &HTTPConfig, tx: SyncSender<ProtoMessage>) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(SocketAddr::new(config.address, config.port))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Self {
+            listener,
+            tx,
+            max_body_size: config.max_body_size as usize,
+        })
+    }
+
+    /// blocks current thread serving http requests until stopped
+    pub fn run(&self, stop: StopSignal) {
+        info!(
+            "Starting HTTP listener thread with id: {:?}, listening to {:?}",
+            thread::current().id(),
+            self.listener.local_addr().unwrap()
+        );
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Unable to create runtime");
+
+        let listener = self.listener.try_clone().expect("Unable to clone socket");
+        let tx = self.tx.clone();
+        let max_body_size = self.max_body_size;
+        runtime.block_on(async move {
+            let make_service = make_service_fn(move |_| {
+                let tx = tx.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req| {
+                        handle(req, tx.clone(), max_body_size)
+                    }))
+                }
+            });
+
+            match Server::from_tcp(listener) {
+                Ok(builder) => {
+                    let shutdown = async move {
+                        while !stop.is_stopped() {
+                            tokio::time::sleep(STOP_POLL_INTERVAL).await;
+                        }
+                    };
+                    let server = builder.serve(make_service).with_graceful_shutdown(shutdown);
+                    if let Err(err) = server.await {
+                        error!("HTTP server stopped {:?}", err);
+                    }
+                }
+                Err(err) => error!("Unable to start HTTP server {:?}", err),
+            }
+        });
+        info!(
+            "Listener thread with id: {:?} stopped",
+            thread::current().id()
+        );
+    }
+}
+
+#[derive(Debug)]
+enum IngestError {
+    NotFound,
+    MethodNotAllowed,
+    UnsupportedMediaType,
+    PayloadTooLarge,
+    Decode(String),
+    Read(hyper::Error),
+    /// anything dropped, messages accepted before pipeline filled up stay accepted
+    PipelineFull {
+        accepted: usize,
+        dropped: usize,
+    },
+}
+
+impl IngestError {
+    fn into_response(self) -> Response<Body> {
+        let (status, message) = match &self {
+            IngestError::NotFound => (StatusCode::NOT_FOUND, "not found".to_string()),
+            IngestError::MethodNotAllowed => (
+                StatusCode::METHOD_NOT_ALLOWED,
+                "only POST is supported".to_string(),
+            ),
+            IngestError::UnsupportedMediaType => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "expected application/x-protobuf or application/json".to_string(),
+            ),
+            IngestError::PayloadTooLarge => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "request body too large".to_string(),
+            ),
+            IngestError::Decode(err) => (StatusCode::BAD_REQUEST, err.clone()),
+            IngestError::Read(err) => (StatusCode::BAD_REQUEST, err.to_string()),
+            IngestError::PipelineFull { accepted, dropped } => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!(
+                    "pipeline is full, {} of {} messages accepted",
+                    accepted,
+                    accepted + dropped
+                ),
+            ),
+        };
+
+        let mut response = Response::new(Body::from(message));
+        *response.status_mut() = status;
+        if let IngestError::PipelineFull { .. } = self {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, hyper::header::HeaderValue::from_static("1"));
+        }
+        response
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Encoding {
+    Protobuf,
+    Json,
+}
+
+impl Encoding {
+    /// protobuf is assumed when content type is missing
+    fn from_content_type(content_type: Option<&str>) -> Result<Self, IngestError> {
+        let mime = match content_type {
+            None => return Ok(Encoding::Protobuf),
+            Some(value) => value.split(';').next().unwrap_or("").trim(),
+        };
+        match mime {
+            "application/x-protobuf" | "application/protobuf" | "application/octet-stream" => {
+                Ok(Encoding::Protobuf)
+            }
+            "application/json" => Ok(Encoding::Json),
+            _ => Err(IngestError::UnsupportedMediaType),
+        }
+    }
+}
+
+async fn handle(
+    req: Request<Body>,
+    tx: SyncSender<ProtoMessage>,
+    max_body_size: usize,
+) -> Result<Response<Body>, Infallible> {
+    match ingest(req, &tx, max_body_size).await {
+        Ok(accepted) => {
+            trace!("{} messages accepted over HTTP", accepted);
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::ACCEPTED;
+            Ok(response)
+        }
+        Err(err) => {
+            warn!("Unable to ingest HTTP request, {:?}", err);
+            Ok(err.into_response())
+        }
+    }
+}
+
+async fn ingest(
+    req: Request<Body>,
+    tx: &SyncSender<ProtoMessage>,
+    max_body_size: usize,
+) -> Result<usize, IngestError> {
+    if req.uri().path() != INGEST_PATH {
+        return Err(IngestError::NotFound);
+    }
+    if req.method() != Method::POST {
+        return Err(IngestError::MethodNotAllowed);
+    }
+    let encoding = Encoding::from_content_type(
+        req.headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok()),
+    )?;
+    let declared_length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if let Some(length) = declared_length {
+        if length > max_body_size {
+            return Err(IngestError::PayloadTooLarge);
+        }
+    }
+
+    let body = read_body(req.into_body(), max_body_size).await?;
+    let messages = decode_body(&encoding, body)?;
+    forward(messages, tx)
+}
+
+/// once pipeline fills up the rest of the batch is dropped and request fails,
+/// error tells how many messages were accepted, so that a retrying client can skip them
+fn forward(
+    messages: Vec<ProtoMessage>,
+    tx: &SyncSender<ProtoMessage>,
+) -> Result<usize, IngestError> {
+    let total = messages.len();
+    let mut accepted = 0;
+    for msg in messages {
+        match tx.try_send(msg) {
+            Ok(_) => accepted += 1,
+            Err(TrySendError::Full(_)) => {
+                return Err(IngestError::PipelineFull {
+                    accepted,
+                    dropped: total - accepted,
+                })
+            }
+            Err(TrySendError::Disconnected(_)) => registry_gone(),
+        }
+    }
+
+    Ok(accepted)
+}
+
+/// reads whole body, giving up as soon as it exceeds the limit
+async fn read_body(mut body: Body, max_body_size: usize) -> Result<BytesMut, IngestError> {
+    let mut buf = BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(IngestError::Read)?;
+        if buf.len() + chunk.len() > max_body_size {
+            return Err(IngestError::PayloadTooLarge);
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(buf)
+}
+
+/// whole body is decoded before anything is forwarded, so invalid one is rejected as a whole
+fn decode_body(encoding: &Encoding, body: BytesMut) -> Result<Vec<ProtoMessage>, IngestError> {
+    match encoding {
+        Encoding::Protobuf => decode_protobuf(body),
+        Encoding::Json => decode_json(&body),
+    }
+}
+
+/// body is a sequence of length-delimited requests, same framing as TCP listener uses
+fn decode_protobuf(mut body: BytesMut) -> Result<Vec<ProtoMessage>, IngestError> {
+    let mut messages = Vec::new();
+    while body.has_remaining() {
+        let request = ProtoRequest::decode_length_delimited(&mut body)
+            .map_err(|err| IngestError::Decode(format!("{}", err)))?;
+        if let Some(msg) = request.message {
+            messages.push(msg);
+        }
+    }
+    Ok(messages)
+}
+
+/// body is a single `ApmV1Action` object or an array of them
+fn decode_json(body: &[u8]) -> Result<Vec<ProtoMessage>, IngestError> {
+    let actions = match serde_json::from_slice(body) {
+        Ok(JsonBody::One(action)) => vec![action],
+        Ok(JsonBody::Many(actions)) => actions,
+        Err(err) => return Err(IngestError::Decode(format!("{}", err))),
+    };
+    Ok(actions
+        .into_iter()
+        .map(|action| ProtoMessage::ApmV1Action(ApmV1Action::from(action)))
+        .collect())
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonBody {
+    One(JsonAction),
+    Many(Vec<JsonAction>),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonAction {
+    realm: String,
+    application: String,
+    #[serde(default)]
+    application_hash: String,
+    action_kind: String,
+    action_name: String,
+    total_us: u64,
+    #[serde(default)]
+    additional_dimensions: Vec<JsonTag>,
+    #[serde(default)]
+    measurements: Vec<JsonMeasurement>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonTag {
+    key: String,
+    value: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonMeasurement {
+    name: String,
+    took_us: u64,
+}
+
+impl From<JsonAction> for ApmV1Action {
+    fn from(a: JsonAction) -> Self {
+        Self {
+            realm: a.realm,
+            application: a.application,
+            application_hash: a.application_hash,
+            action_kind: a.action_kind,
+            action_name: a.action_name,
+            total_us: a.total_us,
+            additional_dimensions: a
+                .additional_dimensions
+                .into_iter()
+                .map(|t| ProtoTag {
+                    key: t.key,
+                    value: t.value,
+                })
+                .collect(),
+            measurements: a
+                .measurements
+                .into_iter()
+                .map(|m| ProtoMeasurement {
+                    name: m.name,
+                    took_us: m.took_us,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::workers::server::listeners::http::{
+        decode_body, forward, handle, Encoding, IngestError,
+    };
+    use hyper::{Body, Request, StatusCode};
+    use palantir_proto::palantir::apm::v1::action::ApmV1Action;
+    use palantir_proto::palantir::request::request::Message as ProtoMessage;
+    use palantir_proto::palantir::request::Request as ProtoRequest;
+    use palantir_proto::prost::bytes::BytesMut;
+    use palantir_proto::prost::Message;
+    use std::sync::mpsc::sync_channel;
+
+    fn action(name: &str) -> ApmV1Action {
+        ApmV1Action {
+            realm: "realm".to_string(),
+            application: "application".to_string(),
+            application_hash: "3fde5".to_string(),
+            action_kind: "http".to_string(),
+            action_name: name.to_string(),
+            total_us: 55_000,
+            additional_dimensions: vec![],
+            measurements: vec![],
+        }
+    }
+
+    fn protobuf_body(names: &[&str]) -> BytesMut {
+        let mut buf = BytesMut::new();
+        for name in names {
+            ProtoRequest {
+                message: Some(ProtoMessage::ApmV1Action(action(name))),
+            }
+            .encode_length_delimited(&mut buf)
+            .unwrap();
+        }
+        buf
+    }
+
+    fn post(content_type: &str, body: Vec<u8>) -> Request<Body> {
+        Request::post("/api/v1/ingest")
+            .header("content-type", content_type)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_content_type() {
+        assert_eq!(
+            Encoding::from_content_type(None).unwrap(),
+            Encoding::Protobuf
+        );
+        assert_eq!(
+            Encoding::from_content_type(Some("application/json; charset=utf-8")).unwrap(),
+            Encoding::Json
+        );
+        assert!(Encoding::from_content_type(Some("text/plain")).is_err());
+    }
+
+    #[test]
+    fn test_decode_protobuf() {
+        let messages = decode_body(&Encoding::Protobuf, protobuf_body(&["a", "b"])).unwrap();
+
+        assert_eq!(
+            messages,
+            vec![
+                ProtoMessage::ApmV1Action(action("a")),
+                ProtoMessage::ApmV1Action(action("b"))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode_protobuf_truncated() {
+        let mut body = protobuf_body(&["a"]);
+        body.truncate(body.len() - 1);
+
+        assert!(decode_body(&Encoding::Protobuf, body).is_err());
+    }
+
+    #[test]
+    fn test_decode_json() {
+        let single = r#"{"realm": "realm", "application": "application",
+            "application_hash": "3fde5", "action_kind": "http",
+            "action_name": "a", "total_us": 55000}"#;
+        let many = format!("[{}, {}]", single, single);
+
+        let messages = decode_body(&Encoding::Json, BytesMut::from(single)).unwrap();
+        assert_eq!(messages, vec![ProtoMessage::ApmV1Action(action("a"))]);
+
+        let messages = decode_body(&Encoding::Json, BytesMut::from(many.as_str())).unwrap();
+        assert_eq!(messages.len(), 2);
+
+        assert!(decode_body(&Encoding::Json, BytesMut::from("{\"realm\": 1}")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_handle_statuses() {
+        let (tx, rx) = sync_channel(1);
+        let body = protobuf_body(&["a"]).to_vec();
+
+        let response = handle(
+            post("application/x-protobuf", body.clone()),
+            tx.clone(),
+            1024,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        let response = handle(
+            post("application/x-protobuf", body.clone()),
+            tx.clone(),
+            1024,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let response = handle(post("application/x-protobuf", body), tx.clone(), 4)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let response = handle(post("application/json", b"{".to_vec()), tx.clone(), 1024)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = handle(post("text/plain", vec![]), tx, 1024).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        assert_eq!(rx.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn test_forward_partially_full() {
+        let (tx, rx) = sync_channel(2);
+        let batch = || {
+            vec![
+                ProtoMessage::ApmV1Action(action("a")),
+                ProtoMessage::ApmV1Action(action("b")),
+                ProtoMessage::ApmV1Action(action("c")),
+            ]
+        };
+
+        assert!(matches!(
+            forward(batch(), &tx),
+            Err(IngestError::PipelineFull {
+                accepted: 2,
+                dropped: 1
+            })
+        ));
+        assert!(matches!(
+            forward(batch(), &tx),
+            Err(IngestError::PipelineFull {
+                accepted: 0,
+                dropped: 3
+            })
+        ));
+        assert_eq!(rx.try_iter().count(), 2);
+        assert_eq!(forward(batch()[..2].to_vec(), &tx).unwrap(), 2);
+    }
+}
@@ -1 +1,70 @@
+use log::{error, trace, warn};
+use palantir_proto::palantir::request::request::Message as ProtoMessage;
+use palantir_proto::palantir::request::Request;
+use palantir_proto::prost::bytes::Buf;
+use palantir_proto::prost::Message;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::SyncSender;
+use std::sync::Arc;
+use std::time::Duration;
+
+mod frame;
+pub mod http;
+pub mod tcp;
 pub mod udp;
+pub mod unix;
+
+/// how often blocked listeners check whether they should stop
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// shared between listener thread and the server, set once to stop the listener
+#[derive(Clone, Default)]
+pub struct StopSignal(Arc<AtomicBool>);
+
+impl StopSignal {
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// read timeout, nothing was received during poll interval
+fn is_timeout(err: &std::io::Error) -> bool {
+    matches!(
+        err.kind(),
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+    )
+}
+
+/// decodes single `Request` and sends it's message to the registry
+/// exits the process if registry is gone
+pub fn forward_request<B: Buf>(buf: B, tx: &SyncSender<ProtoMessage>) {
+    match Request::decode(buf) {
+        Ok(request) => match request.message {
+            Some(msg) => forward_message(msg, tx),
+            None => {
+                warn!("got empty message")
+            }
+        },
+        Err(err) => warn!("Unable to parse request, {:?}", err),
+    }
+}
+
+/// blocks while registry is busy, exits the process if registry is gone
+pub fn forward_message(msg: ProtoMessage, tx: &SyncSender<ProtoMessage>) {
+    match tx.send(msg) {
+        Ok(_) => trace!("Message sent to channel"),
+        Err(_) => registry_gone(),
+    }
+}
+
+fn registry_gone() -> ! {
+    error!("Message can't be sent to channel");
+    // we are unable to operate normally with dropped receiver
+    // and there is also no way to re-init whole data pipeline
+    // TODO introduce mechanism of re-creating data pipeline
+    std::process::exit(1);
+}
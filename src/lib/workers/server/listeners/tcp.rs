@@ -0,0 +1,144 @@
+use crate::config::defs::TCPConfig;
+use crate::workers::server::listeners::frame::serve_connection;
+use crate::workers::server::listeners::{is_timeout, StopSignal, STOP_POLL_INTERVAL};
+use log::{error, info, warn};
+use palantir_proto::palantir::request::request::Message as ProtoMessage;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::SyncSender;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// stream is kept to shut the connection down when listener stops
+type Connection = (TcpStream, JoinHandle<()>);
+
+pub struct TCPListener {
+    listener: TcpListener,
+    buffer_size: usize,
+    max_connections: usize,
+    read_timeout: Duration,
+    tx: SyncSender<ProtoMessage>,
+}
+
+impl TCPListener {
+    /// Ok  -> successfully bound to socket
+    /// Err -> was unable to bind to socket
+    pub fn new(config: &TCPConfig, tx: SyncSender<ProtoMessage>) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(SocketAddr::new(config.address, config.port))?;
+        // accept is polled so that listener can be stopped
+        listener.set_nonblocking(true)?;
+
+        Ok(Self {
+            listener,
+            tx,
+            buffer_size: config.buffer_size as usize,
+            max_connections: config.max_connections as usize,
+            read_timeout: Duration::from_millis(config.read_timeout_ms),
+        })
+    }
+
+    /// blocks current thread in accept loop until stopped
+    /// every accepted connection is served by it's own thread until peer closes it or stays idle
+    /// for longer than read timeout, connections still open are closed once listener is stopped
+    pub fn run(&self, stop: StopSignal) {
+        info!(
+            "Starting TCP listener thread with id: {:?}, listening to {:?}",
+            thread::current().id(),
+            self.listener.local_addr().unwrap()
+        );
+        let mut connections: Vec<Connection> = Vec::new();
+        while !stop.is_stopped() {
+            match self.listener.accept() {
+                Ok((stream, origin)) => {
+                    connections.retain(|(_, handle)| !handle.is_finished());
+                    if connections.len() >= self.max_connections {
+                        warn!(
+                            "Too many connections ({}), closing connection from {}",
+                            connections.len(),
+                            origin
+                        );
+                        continue;
+                    }
+                    match self.serve(stream, origin) {
+                        Ok(connection) => connections.push(connection),
+                        Err(err) => {
+                            warn!("Unable to set up connection from {}, {:?}", origin, err)
+                        }
+                    }
+                }
+                Err(err) if is_timeout(&err) => thread::sleep(STOP_POLL_INTERVAL),
+                Err(err) => {
+                    warn!("Unable to accept connection {:?}", err)
+                }
+            }
+        }
+
+        // connection threads are blocked in reads, shutdown wakes them up
+        for (stream, handle) in connections {
+            let _ = stream.shutdown(Shutdown::Both);
+            if handle.join().is_err() {
+                error!("Connection thread panicked");
+            }
+        }
+        info!(
+            "Listener thread with id: {:?} stopped",
+            thread::current().id()
+        );
+    }
+
+    fn serve(&self, stream: TcpStream, origin: SocketAddr) -> std::io::Result<Connection> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(self.read_timeout))?;
+        let control = stream.try_clone()?;
+        let tx = self.tx.clone();
+        let buffer_size = self.buffer_size;
+        let handle = thread::spawn(move || serve_connection(stream, origin, buffer_size, tx));
+        Ok((control, handle))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::defs::TCPConfig;
+    use crate::workers::server::listeners::tcp::TCPListener;
+    use crate::workers::server::listeners::StopSignal;
+    use std::io::Read;
+    use std::net::{IpAddr, Ipv4Addr, TcpStream};
+    use std::sync::mpsc::sync_channel;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_connection_limit_and_stop() {
+        let (tx, _rx) = sync_channel(1);
+        let config = TCPConfig {
+            address: IpAddr::from(Ipv4Addr::LOCALHOST),
+            port: 0,
+            buffer_size: 4096,
+            max_connections: 1,
+            read_timeout_ms: 60_000,
+        };
+        let listener = TCPListener::new(&config, tx).unwrap();
+        let address = listener.listener.local_addr().unwrap();
+        let stop = StopSignal::default();
+        let listener_stop = stop.clone();
+        let handle = thread::spawn(move || listener.run(listener_stop));
+
+        let mut first = TcpStream::connect(address).unwrap();
+        // let listener pick up the first connection before the second one
+        thread::sleep(Duration::from_millis(500));
+        let mut second = TcpStream::connect(address).unwrap();
+        second
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(second.read(&mut [0u8; 1]).unwrap(), 0);
+
+        // idle first connection doesn't keep listener from stopping
+        stop.stop();
+        handle.join().unwrap();
+        first
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(first.read(&mut [0u8; 1]).unwrap(), 0);
+    }
+}
@@ -1,27 +1,26 @@
 use crate::config::defs::UDPConfig;
-use log::{error, info, trace, warn};
+use crate::workers::server::listeners::{
+    forward_request, is_timeout, StopSignal, STOP_POLL_INTERVAL,
+};
+use log::{info, trace, warn};
 use palantir_proto::palantir::request::request::Message as ProtoMessage;
-use palantir_proto::palantir::request::Request;
 use palantir_proto::prost::bytes::BytesMut;
-use palantir_proto::prost::Message;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
-use std::sync::mpsc::Sender;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::mpsc::SyncSender;
 use std::thread;
 
 pub struct UDPListener {
     socket: UdpSocket,
     buffer_size: usize,
-    tx: Sender<ProtoMessage>,
+    tx: SyncSender<ProtoMessage>,
 }
 
 impl UDPListener {
     /// Ok  -> successfully bound to socket
     /// Err -> was unable to bind to socket
-    pub fn new(config: &UDPConfig, tx: Sender<ProtoMessage>) -> std::io::Result<Self> {
-        let socket = UdpSocket::bind(SocketAddr::new(
-            IpAddr::from(Ipv4Addr::new(127, 0, 0, 1)),
-            config.port,
-        ))?;
+    pub fn new(config: &UDPConfig, tx: SyncSender<ProtoMessage>) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind(SocketAddr::new(config.address, config.port))?;
+        socket.set_read_timeout(Some(STOP_POLL_INTERVAL))?;
 
         Ok(Self {
             socket,
@@ -30,15 +29,15 @@ impl UDPListener {
         })
     }
 
-    /// blocks current thread in socket reading loop
-    pub fn run(&self) {
+    /// blocks current thread in socket reading loop until stopped
+    pub fn run(&self, stop: StopSignal) {
         info!(
             "Starting UDP listener thread with id: {:?}, listening to {:?}",
             thread::current().id(),
             self.socket.local_addr().unwrap()
         );
         let overflow_size = self.buffer_size + 1;
-        loop {
+        while !stop.is_stopped() {
             let mut buf = BytesMut::with_capacity(overflow_size);
             buf.resize(overflow_size, 0);
 
@@ -51,33 +50,17 @@ impl UDPListener {
                     }
 
                     buf.resize(bytes_read, 0);
-                    match Request::decode(buf) {
-                        Ok(request) => {
-                            match request.message {
-                                Some(msg) => {
-                                    match self.tx.send(msg) {
-                                        Ok(_) => trace!("Message sent to channel"),
-                                        Err(_) => {
-                                            error!("Message can't be sent to channel");
-                                            // we are unable to operate normally with dropped receiver
-                                            // and there is also no way to re-init whole data pipeline
-                                            // TODO introduce mechanism of re-creating data pipeline
-                                            std::process::exit(1);
-                                        }
-                                    }
-                                }
-                                None => {
-                                    warn!("got empty message")
-                                }
-                            }
-                        }
-                        Err(err) => warn!("Unable to parse request, {:?}", err),
-                    }
+                    forward_request(buf, &self.tx);
                 }
+                Err(err) if is_timeout(&err) => {}
                 Err(err) => {
                     warn!("Unable to read from socket {:?}", err)
                 }
             }
         }
+        info!(
+            "Listener thread with id: {:?} stopped",
+            thread::current().id()
+        );
     }
 }
@@ -0,0 +1,241 @@
+use crate::config::defs::{UnixDatagramConfig, UnixStreamConfig};
+use crate::workers::server::listeners::frame::serve_connection;
+use crate::workers::server::listeners::{
+    forward_request, is_timeout, StopSignal, STOP_POLL_INTERVAL,
+};
+use log::{info, trace, warn};
+use palantir_proto::palantir::request::request::Message as ProtoMessage;
+use palantir_proto::prost::bytes::BytesMut;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixDatagram, UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::SyncSender;
+use std::thread;
+
+pub struct UnixDatagramListener {
+    socket: UnixDatagram,
+    path: PathBuf,
+    buffer_size: usize,
+    tx: SyncSender<ProtoMessage>,
+}
+
+impl UnixDatagramListener {
+    /// Ok  -> successfully bound to socket
+    /// Err -> was unable to bind to socket
+    pub fn new(config: &UnixDatagramConfig, tx: SyncSender<ProtoMessage>) -> std::io::Result<Self> {
+        remove_stale_socket(&config.path, |path| UnixDatagram::unbound()?.connect(path))?;
+        // constructed right after bind, so that socket file is removed on drop if set up fails
+        let listener = Self {
+            socket: UnixDatagram::bind(&config.path)?,
+            path: config.path.clone(),
+            tx,
+            buffer_size: config.buffer_size as usize,
+        };
+        listener.socket.set_read_timeout(Some(STOP_POLL_INTERVAL))?;
+        set_mode(&config.path, config.mode)?;
+
+        Ok(listener)
+    }
+
+    /// blocks current thread in socket reading loop until stopped
+    pub fn run(&self, stop: StopSignal) {
+        info!(
+            "Starting unix datagram listener thread with id: {:?}, listening to {:?}",
+            thread::current().id(),
+            self.path
+        );
+        let overflow_size = self.buffer_size + 1;
+        while !stop.is_stopped() {
+            let mut buf = BytesMut::with_capacity(overflow_size);
+            buf.resize(overflow_size, 0);
+
+            match self.socket.recv(&mut buf) {
+                Ok(bytes_read) => {
+                    trace!("Read {} bytes from {:?}", bytes_read, self.path);
+                    if bytes_read == overflow_size {
+                        warn!("Request size too large, skipping");
+                        continue;
+                    }
+
+                    buf.resize(bytes_read, 0);
+                    forward_request(buf, &self.tx);
+                }
+                Err(err) if is_timeout(&err) => {}
+                Err(err) => {
+                    warn!("Unable to read from socket {:?}", err)
+                }
+            }
+        }
+        info!(
+            "Listener thread with id: {:?} stopped",
+            thread::current().id()
+        );
+    }
+}
+
+impl Drop for UnixDatagramListener {
+    fn drop(&mut self) {
+        remove_socket_file(&self.path);
+    }
+}
+
+pub struct UnixStreamListener {
+    listener: UnixListener,
+    path: PathBuf,
+    buffer_size: usize,
+    tx: SyncSender<ProtoMessage>,
+}
+
+impl UnixStreamListener {
+    /// Ok  -> successfully bound to socket
+    /// Err -> was unable to bind to socket
+    pub fn new(config: &UnixStreamConfig, tx: SyncSender<ProtoMessage>) -> std::io::Result<Self> {
+        remove_stale_socket(&config.path, |path| UnixStream::connect(path).map(|_| ()))?;
+        // constructed right after bind, so that socket file is removed on drop if set up fails
+        let listener = Self {
+            listener: UnixListener::bind(&config.path)?,
+            path: config.path.clone(),
+            tx,
+            buffer_size: config.buffer_size as usize,
+        };
+        // accept is polled so that listener can be stopped
+        listener.listener.set_nonblocking(true)?;
+        set_mode(&config.path, config.mode)?;
+
+        Ok(listener)
+    }
+
+    /// blocks current thread in accept loop until stopped
+    /// every accepted connection is served by it's own thread until peer closes it
+    pub fn run(&self, stop: StopSignal) {
+        info!(
+            "Starting unix stream listener thread with id: {:?}, listening to {:?}",
+            thread::current().id(),
+            self.path
+        );
+        while !stop.is_stopped() {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(err) = stream.set_nonblocking(false) {
+                        warn!("Unable to set up connection, {:?}", err);
+                        continue;
+                    }
+                    let tx = self.tx.clone();
+                    let buffer_size = self.buffer_size;
+                    let origin = self.path.clone();
+                    thread::spawn(move || serve_connection(stream, origin, buffer_size, tx));
+                }
+                Err(err) if is_timeout(&err) => thread::sleep(STOP_POLL_INTERVAL),
+                Err(err) => {
+                    warn!("Unable to accept connection {:?}", err)
+                }
+            }
+        }
+        info!(
+            "Listener thread with id: {:?} stopped",
+            thread::current().id()
+        );
+    }
+}
+
+impl Drop for UnixStreamListener {
+    fn drop(&mut self) {
+        remove_socket_file(&self.path);
+    }
+}
+
+/// removes socket file left by a previous run
+/// socket still served by someone (probe connects) and non-socket files are never removed
+fn remove_stale_socket<F>(path: &Path, probe: F) -> std::io::Result<()>
+where
+    F: Fn(&Path) -> std::io::Result<()>,
+{
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+
+    if !metadata.file_type().is_socket() {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{:?} exists and is not a socket", path),
+        ));
+    }
+
+    match probe(path) {
+        Ok(_) => Err(Error::new(
+            ErrorKind::AddrInUse,
+            format!("{:?} is served by another process", path),
+        )),
+        Err(err) if err.kind() == ErrorKind::ConnectionRefused => {
+            info!("Removing stale socket file {:?}", path);
+            fs::remove_file(path)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+fn set_mode(path: &Path, mode: Option<u32>) -> std::io::Result<()> {
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+fn remove_socket_file(path: &Path) {
+    if let Err(err) = fs::remove_file(path) {
+        warn!("Unable to remove socket file {:?}, {:?}", path, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::workers::server::listeners::unix::remove_stale_socket;
+    use std::io::ErrorKind;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::PathBuf;
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("palantir-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_remove_stale_socket() {
+        let path = socket_path("stale");
+        drop(UnixListener::bind(&path).unwrap());
+
+        remove_stale_socket(&path, |path| UnixStream::connect(path).map(|_| ())).unwrap();
+
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_keep_socket_in_use() {
+        let path = socket_path("in-use");
+        let _listener = UnixListener::bind(&path).unwrap();
+
+        let err =
+            remove_stale_socket(&path, |path| UnixStream::connect(path).map(|_| ())).unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::AddrInUse);
+        assert!(path.exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_keep_regular_file() {
+        let path = socket_path("regular");
+        std::fs::write(&path, b"not a socket").unwrap();
+
+        let err = remove_stale_socket(&path, |_| Ok(())).unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        assert!(path.exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
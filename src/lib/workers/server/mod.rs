@@ -1,39 +1,153 @@
 use crate::config::defs::ListenerType;
+use listeners::http::HTTPListener;
+use listeners::tcp::TCPListener;
 use listeners::udp::UDPListener;
+use listeners::unix::{UnixDatagramListener, UnixStreamListener};
+use listeners::StopSignal;
+use log::{error, info};
 use palantir_proto::palantir::request::request::Message as ProtoMessage;
 use std::io::Result as IOResult;
-use std::sync::mpsc::Sender;
+use std::sync::mpsc::SyncSender;
 use std::thread;
 use std::thread::JoinHandle;
 
 mod listeners;
 
-pub struct Server<'a> {
-    listeners: &'a Vec<ListenerType>,
-    tx: Sender<ProtoMessage>,
+struct RunningListener {
+    config: ListenerType,
+    stop: StopSignal,
+    handle: JoinHandle<()>,
 }
 
-impl Server<'_> {
-    pub fn new(listeners: &'static Vec<ListenerType>, tx: Sender<ProtoMessage>) -> Self {
-        return Self { listeners, tx };
+pub struct Server {
+    running: Vec<RunningListener>,
+    tx: SyncSender<ProtoMessage>,
+}
+
+impl Server {
+    pub fn new(tx: SyncSender<ProtoMessage>) -> Self {
+        Self {
+            running: Vec::new(),
+            tx,
+        }
     }
 
-    pub fn schedule(&self) -> IOResult<Vec<JoinHandle<()>>> {
-        let mut threads = Vec::new();
-        for config in self.listeners {
-            match config {
-                ListenerType::UDP(udp_config) => {
-                    let listener = UDPListener::new(udp_config, self.tx.clone())?;
-                    threads.push(thread::spawn(move || {
-                        listener.run();
-                    }));
-                }
-                ListenerType::TCP(_) => {
-                    panic!("not implemented")
+    /// brings running listeners in line with config
+    /// listeners with unchanged config keep running, changed ones are re-bound
+    /// Err -> first bind error, the rest of listeners are still applied
+    pub fn apply(&mut self, listeners: &[ListenerType]) -> IOResult<()> {
+        let (keep, stop): (Vec<RunningListener>, Vec<RunningListener>) = self
+            .running
+            .drain(..)
+            .partition(|running| listeners.contains(&running.config));
+        self.running = keep;
+
+        // stop first so re-bound listeners can take the same address
+        for running in stop {
+            info!("Stopping listener {:?}", running.config);
+            running.stop.stop();
+            if running.handle.join().is_err() {
+                error!("Listener {:?} panicked", running.config);
+            }
+        }
+
+        let mut result = Ok(());
+        for config in listeners {
+            if self.running.iter().any(|running| running.config == *config) {
+                continue;
+            }
+            match self.start(config) {
+                Ok(running) => self.running.push(running),
+                Err(err) => {
+                    error!("Unable to start listener {:?}, {:?}", config, err);
+                    if result.is_ok() {
+                        result = Err(err);
+                    }
                 }
             }
         }
 
-        return Ok(threads);
+        result
+    }
+
+    fn start(&self, config: &ListenerType) -> IOResult<RunningListener> {
+        let stop = StopSignal::default();
+        let listener_stop = stop.clone();
+        let handle = match config {
+            ListenerType::UDP(udp_config) => {
+                let listener = UDPListener::new(udp_config, self.tx.clone())?;
+                thread::spawn(move || {
+                    listener.run(listener_stop);
+                })
+            }
+            ListenerType::TCP(tcp_config) => {
+                let listener = TCPListener::new(tcp_config, self.tx.clone())?;
+                thread::spawn(move || {
+                    listener.run(listener_stop);
+                })
+            }
+            ListenerType::UnixDatagram(unix_config) => {
+                let listener = UnixDatagramListener::new(unix_config, self.tx.clone())?;
+                thread::spawn(move || {
+                    listener.run(listener_stop);
+                })
+            }
+            ListenerType::UnixStream(unix_config) => {
+                let listener = UnixStreamListener::new(unix_config, self.tx.clone())?;
+                thread::spawn(move || {
+                    listener.run(listener_stop);
+                })
+            }
+            ListenerType::HTTP(http_config) => {
+                let listener = HTTPListener::new(http_config, self.tx.clone())?;
+                thread::spawn(move || {
+                    listener.run(listener_stop);
+                })
+            }
+        };
+
+        Ok(RunningListener {
+            config: config.clone(),
+            stop,
+            handle,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::defs::{ListenerType, UDPConfig};
+    use crate::workers::server::Server;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::mpsc::sync_channel;
+
+    fn udp(buffer_size: u16) -> ListenerType {
+        ListenerType::UDP(UDPConfig {
+            address: IpAddr::from(Ipv4Addr::LOCALHOST),
+            port: 0,
+            buffer_size,
+        })
+    }
+
+    #[test]
+    fn test_apply_keeps_unchanged_listeners() {
+        let (tx, _rx) = sync_channel(1);
+        let mut server = Server::new(tx);
+
+        server.apply(&[udp(1024)]).unwrap();
+        let first_thread = server.running[0].handle.thread().id();
+        server.apply(&[udp(1024), udp(2048)]).unwrap();
+
+        assert_eq!(server.running.len(), 2);
+        assert_eq!(server.running[0].handle.thread().id(), first_thread);
+
+        server.apply(&[udp(2048)]).unwrap();
+
+        assert_eq!(server.running.len(), 1);
+        assert_eq!(server.running[0].config, udp(2048));
+
+        server.apply(&[]).unwrap();
+
+        assert!(server.running.is_empty());
     }
 }